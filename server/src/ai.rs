@@ -0,0 +1,457 @@
+//! Computer players. They only ever look at a game through the `Game` trait,
+//! so they can sit in any room.
+
+use game::{Game, GameOutcome, GameTurnMessage};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// past this many legal moves a full search takes forever, so only look this
+/// many plies ahead: enough to take a win or stop one
+const BIG_BOARD_DEPTH: usize = 2;
+
+/// bigger than any depth we search, so quicker wins always score higher
+const WIN_SCORE: i32 = 1000;
+
+/// how strong a computer player is, picked when it is added to a room.
+/// clients send it as `{"difficulty": "mcts", "playouts": 500}`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "difficulty", rename_all = "snake_case")]
+pub enum Difficulty {
+    /// any legal move
+    Random,
+    /// takes a win if it has one, otherwise avoids handing the opponent one
+    WinOrBlock,
+    /// monte carlo tree search, stronger with more playouts
+    Mcts {
+        #[serde(default = "default_playouts")]
+        playouts: usize,
+    },
+    /// minimax, see `perfect_move`
+    #[default]
+    Perfect,
+}
+
+/// the most playouts a computer player may be asked for. that many take the
+/// best part of twenty seconds a move on the biggest boards, tying up one of
+/// the threads bots think on all the while
+pub const MAX_PLAYOUTS: usize = 10_000;
+
+fn default_playouts() -> usize {
+    1000
+}
+
+/// the move a computer player of this difficulty makes, `None` if there is
+/// nothing to play
+pub fn choose_move<R: Rng>(
+    game: &dyn Game,
+    difficulty: Difficulty,
+    rng: &mut R,
+) -> Option<GameTurnMessage> {
+    match difficulty {
+        Difficulty::Random => random_move(game, rng),
+        Difficulty::WinOrBlock => win_or_block_move(game, rng),
+        Difficulty::Mcts { playouts } => mcts_move(game, playouts, rng),
+        Difficulty::Perfect => perfect_move(game),
+    }
+}
+
+pub fn random_move<R: Rng>(game: &dyn Game, rng: &mut R) -> Option<GameTurnMessage> {
+    game.legal_moves().choose(rng).cloned()
+}
+
+/// does `player` win by playing `turn`
+fn wins_with(game: &dyn Game, player: usize, turn: &GameTurnMessage) -> bool {
+    let mut next = game.box_clone();
+    next.apply_move(player, turn).is_ok() && next.outcome().winner() == Some(player)
+}
+
+/// one ply of lookahead: win now if possible, otherwise pick a random move
+/// that doesn't leave the opponent a win
+pub fn win_or_block_move<R: Rng>(game: &dyn Game, rng: &mut R) -> Option<GameTurnMessage> {
+    let me = game.current_player()?;
+    let moves = game.legal_moves();
+    if let Some(winning) = moves.iter().find(|turn| wins_with(game, me, turn)) {
+        return Some(winning.clone());
+    }
+    let safe: Vec<&GameTurnMessage> = moves
+        .iter()
+        .filter(|turn| {
+            let mut next = game.box_clone();
+            if next.apply_move(me, turn).is_err() {
+                return false;
+            }
+            match next.current_player() {
+                Some(them) => !next
+                    .legal_moves()
+                    .iter()
+                    .any(|reply| wins_with(&*next, them, reply)),
+                None => true,
+            }
+        })
+        .collect();
+    match safe.choose(rng) {
+        Some(turn) => Some((*turn).clone()),
+        None => moves.choose(rng).cloned(),
+    }
+}
+
+/// how much UCT favours trying less visited moves
+const EXPLORATION: f64 = 1.41;
+
+/// a position in the search tree
+struct Node {
+    game: Box<dyn Game>,
+    /// who played `turn` to get here
+    mover: Option<usize>,
+    turn: Option<GameTurnMessage>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    untried: Vec<GameTurnMessage>,
+    visits: f64,
+    /// 1 for each playout `mover` won from here, a half for each draw
+    score: f64,
+}
+
+impl Node {
+    fn new(
+        game: Box<dyn Game>,
+        mover: Option<usize>,
+        turn: Option<GameTurnMessage>,
+        parent: Option<usize>,
+    ) -> Self {
+        let untried = game.candidate_moves();
+        Node {
+            game,
+            mover,
+            turn,
+            parent,
+            children: Vec::new(),
+            untried,
+            visits: 0.0,
+            score: 0.0,
+        }
+    }
+}
+
+/// monte carlo tree search with UCT, running `playouts` random games
+pub fn mcts_move<R: Rng>(game: &dyn Game, playouts: usize, rng: &mut R) -> Option<GameTurnMessage> {
+    game.current_player()?;
+    let mut root = Node::new(game.box_clone(), None, None, None);
+    // deeper down a refused candidate just gets skipped, but the move we
+    // answer with has to be legal
+    root.untried = game.legal_moves();
+    let mut nodes = vec![root];
+    if nodes[0].untried.is_empty() {
+        return None;
+    }
+
+    for _ in 0..playouts.max(1) {
+        // walk down through fully expanded nodes
+        let mut id = 0;
+        while nodes[id].untried.is_empty() && !nodes[id].children.is_empty() {
+            id = best_child(&nodes, id);
+        }
+
+        // try one new move from here
+        if !nodes[id].untried.is_empty() {
+            let i = rng.gen_range(0, nodes[id].untried.len());
+            let turn = nodes[id].untried.swap_remove(i);
+            if let Some(mover) = nodes[id].game.current_player() {
+                let mut next = nodes[id].game.box_clone();
+                if next.apply_move(mover, &turn).is_ok() {
+                    nodes.push(Node::new(next, Some(mover), Some(turn), Some(id)));
+                    let child = nodes.len() - 1;
+                    nodes[id].children.push(child);
+                    id = child;
+                }
+            }
+        }
+
+        let outcome = random_playout(nodes[id].game.box_clone(), rng);
+
+        let mut walk = Some(id);
+        while let Some(n) = walk {
+            nodes[n].visits += 1.0;
+            if let Some(mover) = nodes[n].mover {
+                nodes[n].score += match outcome {
+                    GameOutcome::Won { player, .. } if player == mover => 1.0,
+                    GameOutcome::Draw => 0.5,
+                    _ => 0.0,
+                };
+            }
+            walk = nodes[n].parent;
+        }
+    }
+
+    let most_visited = nodes[0].children.iter().max_by(|a, b| {
+        nodes[**a]
+            .visits
+            .partial_cmp(&nodes[**b].visits)
+            .expect("visit counts are never NaN")
+    })?;
+    nodes[*most_visited].turn.clone()
+}
+
+/// the child of `id` with the best UCT score
+fn best_child(nodes: &[Node], id: usize) -> usize {
+    let parent_visits = nodes[id].visits.max(1.0);
+    let uct = |child: usize| {
+        let node = &nodes[child];
+        if node.visits == 0.0 {
+            return f64::INFINITY;
+        }
+        node.score / node.visits + EXPLORATION * (parent_visits.ln() / node.visits).sqrt()
+    };
+    let mut best = nodes[id].children[0];
+    for &child in &nodes[id].children {
+        if uct(child) > uct(best) {
+            best = child;
+        }
+    }
+    best
+}
+
+/// play random moves until the game ends
+fn random_playout<R: Rng>(mut game: Box<dyn Game>, rng: &mut R) -> GameOutcome {
+    loop {
+        if game.outcome().is_over() {
+            return game.outcome();
+        }
+        let current = match game.current_player() {
+            Some(current) => current,
+            None => return game.outcome(),
+        };
+        // pick candidates at random until one is allowed
+        let mut moves = game.candidate_moves();
+        loop {
+            if moves.is_empty() {
+                return game.outcome();
+            }
+            let turn = moves.swap_remove(rng.gen_range(0, moves.len()));
+            if game.apply_move(current, &turn).is_ok() {
+                break;
+            }
+        }
+    }
+}
+
+/// minimax with alpha-beta pruning. plays perfectly on the classic board,
+/// `None` if there is nothing to play
+pub fn perfect_move(game: &dyn Game) -> Option<GameTurnMessage> {
+    let me = game.current_player()?;
+    let moves = game.legal_moves();
+    // on a big board only the game's candidates are worth a look, e.g. the
+    // squares near the stones already down in gomoku
+    let (max_depth, tries) = if moves.len() > 9 {
+        (BIG_BOARD_DEPTH, game.candidate_moves())
+    } else {
+        (moves.len(), moves.clone())
+    };
+
+    let mut best = None;
+    let mut best_score = -WIN_SCORE - 1;
+    for turn in tries {
+        let mut next = game.box_clone();
+        if next.apply_move(me, &turn).is_err() {
+            continue;
+        }
+        let score = minimax(&*next, me, 1, max_depth, best_score, WIN_SCORE + 1);
+        if score > best_score {
+            best_score = score;
+            best = Some(turn);
+        }
+    }
+    best.or_else(|| moves.into_iter().next())
+}
+
+/// score of `game` for `me`, `depth` plies into the search
+fn minimax(
+    game: &dyn Game,
+    me: usize,
+    depth: usize,
+    max_depth: usize,
+    mut alpha: i32,
+    mut beta: i32,
+) -> i32 {
+    match game.outcome() {
+        GameOutcome::Won { player, .. } if player == me => return WIN_SCORE - depth as i32,
+        GameOutcome::Won { .. } => return depth as i32 - WIN_SCORE,
+        GameOutcome::Draw => return 0,
+        GameOutcome::InProgress => {}
+    }
+    let current = match game.current_player() {
+        Some(current) => current,
+        None => return 0,
+    };
+    if depth >= max_depth {
+        return 0;
+    }
+
+    let maximizing = current == me;
+    let mut best = if maximizing {
+        -WIN_SCORE - 1
+    } else {
+        WIN_SCORE + 1
+    };
+    for turn in game.candidate_moves() {
+        let mut next = game.box_clone();
+        if next.apply_move(current, &turn).is_err() {
+            continue;
+        }
+        let score = minimax(&*next, me, depth + 1, max_depth, alpha, beta);
+        if maximizing {
+            best = best.max(score);
+            alpha = alpha.max(score);
+        } else {
+            best = best.min(score);
+            beta = beta.min(score);
+        }
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::{Cell, Seat};
+    use gomoku::{GomokuGame, GomokuRules};
+    use rand::thread_rng;
+    use tictactoe::TicTacToeGame;
+
+    fn game(position: &str) -> TicTacToeGame {
+        let mut g = TicTacToeGame::from_position(position).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    #[test]
+    fn takes_the_win() {
+        let g = game("xx1/oo1/3 o 3x3 3");
+        let turn = perfect_move(&g).unwrap();
+        assert_eq!(turn.position, vec![2, 1]);
+    }
+
+    #[test]
+    fn blocks_the_win() {
+        let g = game("x2/1o1/x2 o 3x3 3");
+        let turn = perfect_move(&g).unwrap();
+        assert_eq!(turn.position, vec![0, 1]);
+    }
+
+    #[test]
+    fn perfect_players_always_draw() {
+        let mut g: Box<dyn Game> = Box::new(game("3/3/3 x 3x3 3"));
+        while let Some(turn) = perfect_move(&*g) {
+            let current = g.current_player().unwrap();
+            g.apply_move(current, &turn).unwrap();
+        }
+        assert_eq!(g.outcome(), GameOutcome::Draw);
+    }
+
+    #[test]
+    fn still_answers_on_a_big_board() {
+        let mut g = TicTacToeGame::with_size(15, 15, 5).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        for x in 0..4 {
+            g.grid.set(x + 3, 7, Cell::Taken(Seat::One));
+        }
+        g.grid.set(2, 7, Cell::Taken(Seat::Two));
+        g.seating.current_player_turn = Some(2);
+        let turn = perfect_move(&g).unwrap();
+        assert_eq!(turn.position, vec![7, 7]);
+    }
+
+    #[test]
+    fn every_difficulty_takes_an_obvious_win() {
+        let g = game("xx1/oo1/x2 o 3x3 3");
+        let difficulties = vec![
+            Difficulty::WinOrBlock,
+            Difficulty::Mcts { playouts: 500 },
+            Difficulty::Perfect,
+        ];
+        for difficulty in difficulties {
+            let turn = choose_move(&g, difficulty, &mut thread_rng()).unwrap();
+            assert_eq!(turn.position, vec![2, 1], "{:?}", difficulty);
+        }
+    }
+
+    #[test]
+    fn win_or_block_blocks() {
+        let g = game("x2/1o1/x2 o 3x3 3");
+        let turn = win_or_block_move(&g, &mut thread_rng()).unwrap();
+        assert_eq!(turn.position, vec![0, 1]);
+    }
+
+    #[test]
+    fn random_move_is_legal() {
+        let g = game("xox/ox1/oxo x 3x3 3");
+        let turn = random_move(&g, &mut thread_rng()).unwrap();
+        assert_eq!(turn.position, vec![2, 1]);
+    }
+
+    #[test]
+    fn mcts_plays_on_a_big_board() {
+        let mut g = TicTacToeGame::with_size(7, 7, 4).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        let turn = mcts_move(&g, 50, &mut thread_rng()).unwrap();
+        assert!(g.legal_moves().contains(&turn));
+    }
+
+    #[test]
+    fn mcts_only_answers_with_legal_renju_moves() {
+        let mut g = GomokuGame::new(GomokuRules {
+            renju: true,
+            ..GomokuRules::default()
+        });
+        g.add_player(1);
+        g.add_player(2);
+        // black's open twos cross at (7, 7), a double three if black goes there
+        let stones = [
+            (5, 7),
+            (0, 0),
+            (6, 7),
+            (14, 0),
+            (7, 5),
+            (0, 14),
+            (7, 6),
+            (14, 14),
+        ];
+        for &(x, y) in &stones {
+            let current = g.current_player().unwrap();
+            let turn = GameTurnMessage {
+                position: vec![x, y],
+            };
+            g.apply_move(current, &turn).unwrap();
+        }
+        let turn = mcts_move(&g, 50, &mut thread_rng()).unwrap();
+        assert_ne!(turn.position, vec![7, 7]);
+        assert!(g.legal_moves().contains(&turn));
+    }
+
+    #[test]
+    fn perfect_plays_gomoku_near_the_stones() {
+        let mut g = GomokuGame::new(GomokuRules::default());
+        g.add_player(1);
+        g.add_player(2);
+        let turn = perfect_move(&g).unwrap();
+        assert_eq!(turn.position, vec![7, 7]);
+        g.apply_move(1, &turn).unwrap();
+        let turn = perfect_move(&g).unwrap();
+        assert!(turn.position.iter().all(|v| (5..=9).contains(v)));
+    }
+
+    #[test]
+    fn difficulty_from_json() {
+        let d: Difficulty = serde_json::from_str(r#"{"difficulty": "mcts"}"#).unwrap();
+        assert_eq!(d, Difficulty::Mcts { playouts: 1000 });
+        let d: Difficulty = serde_json::from_str(r#"{"difficulty": "win_or_block"}"#).unwrap();
+        assert_eq!(d, Difficulty::WinOrBlock);
+    }
+}
@@ -0,0 +1,364 @@
+//! Boards packed into bits so search doesn't allocate. Square (x, y) is bit
+//! `y * STRIDE + x`, whatever the board's width, so every board up to
+//! `MAX_BOARD_SIZE` a side fits in the same 256 bits.
+
+use game::{Cell, Seat};
+use serde::de::{self, Deserialize, Deserializer};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tictactoe::MAX_BOARD_SIZE;
+
+/// bits between the start of one row and the next
+const STRIDE: usize = MAX_BOARD_SIZE;
+
+/// a set of squares
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Bitboard([u64; 4]);
+
+impl Bitboard {
+    /// every square of a `width` by `height` board
+    pub fn full(width: usize, height: usize) -> Bitboard {
+        let mut board = Bitboard::default();
+        for y in 0..height {
+            for x in 0..width {
+                board.insert(x, y);
+            }
+        }
+        board
+    }
+
+    pub fn contains(self, x: usize, y: usize) -> bool {
+        let bit = y * STRIDE + x;
+        self.0[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    pub fn insert(&mut self, x: usize, y: usize) {
+        let bit = y * STRIDE + x;
+        self.0[bit / 64] |= 1 << (bit % 64);
+    }
+
+    pub fn remove(&mut self, x: usize, y: usize) {
+        let bit = y * STRIDE + x;
+        self.0[bit / 64] &= !(1 << (bit % 64));
+    }
+
+    pub fn union(self, other: Bitboard) -> Bitboard {
+        let mut words = self.0;
+        for (word, other) in words.iter_mut().zip(other.0.iter()) {
+            *word |= other;
+        }
+        Bitboard(words)
+    }
+
+    /// the squares in `self` that aren't in `other`
+    pub fn difference(self, other: Bitboard) -> Bitboard {
+        let mut words = self.0;
+        for (word, other) in words.iter_mut().zip(other.0.iter()) {
+            *word &= !other;
+        }
+        Bitboard(words)
+    }
+
+    /// whether every square of `other` is in `self`
+    pub fn contains_all(self, other: Bitboard) -> bool {
+        self.0
+            .iter()
+            .zip(other.0.iter())
+            .all(|(word, other)| word & other == *other)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    pub fn count(self) -> u32 {
+        self.0.iter().map(|word| word.count_ones()).sum()
+    }
+
+    /// the (x, y) of every square, row by row from the top
+    pub fn squares(self) -> Vec<(usize, usize)> {
+        let mut squares = Vec::with_capacity(self.count() as usize);
+        for (i, &word) in self.0.iter().enumerate() {
+            let mut rest = word;
+            while rest != 0 {
+                let bit = i * 64 + rest.trailing_zeros() as usize;
+                squares.push((bit % STRIDE, bit / STRIDE));
+                rest &= rest - 1;
+            }
+        }
+        squares
+    }
+}
+
+/// that a `width` by `height` board fits in a bitboard and `win_length` in
+/// a row fits on it
+pub fn check_size(width: usize, height: usize, win_length: usize) -> Result<(), String> {
+    if width == 0 || height == 0 || width > MAX_BOARD_SIZE || height > MAX_BOARD_SIZE {
+        return Err(format!(
+            "board must be between 1x1 and {}x{}, got {}x{}",
+            MAX_BOARD_SIZE, MAX_BOARD_SIZE, width, height
+        ));
+    }
+    if win_length == 0 || win_length > width.max(height) {
+        return Err(format!(
+            "win length {} does not fit on a {}x{} board",
+            win_length, width, height
+        ));
+    }
+    Ok(())
+}
+
+/// win masks by (width, height, win_length, torus)
+type MaskCache = HashMap<(usize, usize, usize, bool), Rc<Vec<Bitboard>>>;
+
+thread_local! {
+    static WIN_MASKS: RefCell<MaskCache> = RefCell::new(HashMap::new());
+}
+
+/// every run of `win_length` squares on the board, worked out once per size
+pub fn win_masks(width: usize, height: usize, win_length: usize) -> Rc<Vec<Bitboard>> {
+    cached_win_masks(width, height, win_length, false)
+}
+
+/// every run of `win_length` squares on a board whose edges wrap around, so
+/// a line may go off one side and carry on from the other
+pub fn torus_win_masks(width: usize, height: usize, win_length: usize) -> Rc<Vec<Bitboard>> {
+    cached_win_masks(width, height, win_length, true)
+}
+
+fn cached_win_masks(
+    width: usize,
+    height: usize,
+    win_length: usize,
+    torus: bool,
+) -> Rc<Vec<Bitboard>> {
+    WIN_MASKS.with(|cache| {
+        cache
+            .borrow_mut()
+            .entry((width, height, win_length, torus))
+            .or_insert_with(|| Rc::new(build_win_masks(width, height, win_length, torus)))
+            .clone()
+    })
+}
+
+fn build_win_masks(width: usize, height: usize, win_length: usize, torus: bool) -> Vec<Bitboard> {
+    // right, down, down-right and up-right cover every line exactly once
+    let directions: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+    let (w, h) = (width as isize, height as isize);
+    let reach = win_length as isize - 1;
+    let mut masks = Vec::new();
+    for y in 0..h {
+        for x in 0..w {
+            for &(dx, dy) in directions.iter() {
+                let (end_x, end_y) = (x + dx * reach, y + dy * reach);
+                if !torus && (end_x < 0 || end_y < 0 || end_x >= w || end_y >= h) {
+                    continue;
+                }
+                let mut mask = Bitboard::default();
+                for i in 0..=reach {
+                    let (sx, sy) = ((x + dx * i).rem_euclid(w), (y + dy * i).rem_euclid(h));
+                    mask.insert(sx as usize, sy as usize);
+                }
+                // on a torus a line longer than its loop runs into itself, and
+                // one as long as its loop is found again from every square
+                if mask.count() as usize == win_length && !masks.contains(&mask) {
+                    masks.push(mask);
+                }
+            }
+        }
+    }
+    masks
+}
+
+/// the marks on a board, one bitboard per seat, and the squares blocked out
+/// of play. it goes over the wire as the rows of cells clients have always
+/// been sent
+#[derive(Clone, Debug, PartialEq)]
+pub struct Board {
+    width: usize,
+    height: usize,
+    marks: [Bitboard; 2],
+    blocked: Bitboard,
+}
+
+impl Board {
+    pub fn new(width: usize, height: usize) -> Board {
+        Board {
+            width,
+            height,
+            marks: [Bitboard::default(); 2],
+            blocked: Bitboard::default(),
+        }
+    }
+
+    /// a board from rows of cells, top row first
+    pub fn from_rows(rows: &[Vec<Cell>]) -> Result<Board, String> {
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        if width > MAX_BOARD_SIZE || height > MAX_BOARD_SIZE {
+            return Err(format!(
+                "a board can't be bigger than {0}x{0}",
+                MAX_BOARD_SIZE
+            ));
+        }
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("every row of a board has to be the same length".to_owned());
+        }
+        let mut board = Board::new(width, height);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                board.set(x, y, cell);
+            }
+        }
+        Ok(board)
+    }
+
+    pub fn rows(&self) -> Vec<Vec<Cell>> {
+        (0..self.height)
+            .map(|y| (0..self.width).map(|x| self.get(x, y)).collect())
+            .collect()
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Cell {
+        if self.marks[0].contains(x, y) {
+            Cell::Taken(Seat::One)
+        } else if self.marks[1].contains(x, y) {
+            Cell::Taken(Seat::Two)
+        } else if self.blocked.contains(x, y) {
+            Cell::Blocked
+        } else {
+            Cell::Empty
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        for marks in self.marks.iter_mut() {
+            marks.remove(x, y);
+        }
+        self.blocked.remove(x, y);
+        match cell {
+            Cell::Empty => {}
+            Cell::Taken(seat) => self.marks[seat_index(seat)].insert(x, y),
+            Cell::Blocked => self.blocked.insert(x, y),
+        }
+    }
+
+    /// the squares `seat` has marked
+    pub fn marks(&self, seat: Seat) -> Bitboard {
+        self.marks[seat_index(seat)]
+    }
+
+    /// the squares nobody can play on
+    pub fn blocked(&self) -> Bitboard {
+        self.blocked
+    }
+
+    /// the squares nobody has marked yet, leaving out blocked ones
+    pub fn empty(&self) -> Bitboard {
+        let used = self.marks[0].union(self.marks[1]).union(self.blocked);
+        Bitboard::full(self.width, self.height).difference(used)
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.empty().is_empty()
+    }
+
+    /// the first of `masks` one seat has filled, and whose it is
+    pub fn completed_line(&self, masks: &[Bitboard]) -> Option<(Seat, Bitboard)> {
+        for &mask in masks {
+            for &seat in &[Seat::One, Seat::Two] {
+                if self.marks(seat).contains_all(mask) {
+                    return Some((seat, mask));
+                }
+            }
+        }
+        None
+    }
+}
+
+fn seat_index(seat: Seat) -> usize {
+    seat.number() as usize - 1
+}
+
+impl Serialize for Board {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.rows().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Board {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rows = Vec::<Vec<Cell>>::deserialize(deserializer)?;
+        Board::from_rows(&rows).map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squares_come_back_row_by_row() {
+        let mut board = Bitboard::default();
+        board.insert(15, 15);
+        board.insert(2, 0);
+        board.insert(0, 3);
+        assert_eq!(board.squares(), vec![(2, 0), (0, 3), (15, 15)]);
+        assert_eq!(board.count(), 3);
+        board.remove(2, 0);
+        assert!(!board.contains(2, 0));
+    }
+
+    #[test]
+    fn classic_board_has_eight_lines() {
+        assert_eq!(win_masks(3, 3, 3).len(), 8);
+        assert_eq!(win_masks(4, 4, 3).len(), 24);
+        let masks = win_masks(3, 3, 3);
+        let mut board = Board::new(3, 3);
+        for i in 0..3 {
+            board.set(2 - i, i, Cell::Taken(Seat::Two));
+        }
+        let (seat, line) = board.completed_line(&masks).unwrap();
+        assert_eq!(seat, Seat::Two);
+        assert_eq!(line.squares(), vec![(2, 0), (1, 1), (0, 2)]);
+    }
+
+    #[test]
+    fn torus_lines_wrap_around_the_edges() {
+        // every row, column and broken diagonal of a 3x3 torus, once each
+        assert_eq!(torus_win_masks(3, 3, 3).len(), 12);
+        assert_eq!(torus_win_masks(4, 4, 3).len(), 64);
+        let mut board = Board::new(4, 4);
+        for &(x, y) in &[(3, 1), (0, 2), (1, 3)] {
+            board.set(x, y, Cell::Taken(Seat::One));
+        }
+        assert!(board.completed_line(&win_masks(4, 4, 3)).is_none());
+        assert!(board.completed_line(&torus_win_masks(4, 4, 3)).is_some());
+    }
+
+    #[test]
+    fn boards_go_over_the_wire_as_rows() {
+        let mut board = Board::new(3, 2);
+        board.set(1, 0, Cell::Taken(Seat::One));
+        board.set(2, 1, Cell::Taken(Seat::Two));
+        let json = serde_json::to_string(&board).unwrap();
+        assert_eq!(json, "[[0,1,0],[0,0,2]]");
+        let back: Board = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, board);
+        assert!(serde_json::from_str::<Board>("[[0,1],[0]]").is_err());
+        assert_eq!(board.empty().count(), 4);
+        board.set(0, 0, Cell::Blocked);
+        assert_eq!(serde_json::to_string(&board).unwrap(), "[[3,1,0],[0,0,2]]");
+        assert_eq!(board.empty().count(), 3);
+    }
+}
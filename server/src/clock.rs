@@ -0,0 +1,141 @@
+//! Chess style game clocks. Each player's time only runs while it is their
+//! turn, and with an increment they get some back after every move.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// time control picked when a room is created. an increment of 0 is
+/// sudden death, anything else is Fischer
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ClockOptions {
+    pub initial_seconds: u64,
+    #[serde(default)]
+    pub increment_seconds: u64,
+}
+
+/// the clocks of one game, sent to clients in every "board" event
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GameClock {
+    pub initial_ms: u64,
+    pub increment_ms: u64,
+    /// time left for each player id
+    pub remaining_ms: HashMap<usize, u64>,
+    /// whose time is going down right now
+    pub running: Option<usize>,
+    #[serde(skip, default = "Instant::now")]
+    last_update: Instant,
+}
+
+impl GameClock {
+    pub fn new(options: &ClockOptions, now: Instant) -> Self {
+        GameClock {
+            initial_ms: options.initial_seconds * 1000,
+            increment_ms: options.increment_seconds * 1000,
+            remaining_ms: HashMap::new(),
+            running: None,
+            last_update: now,
+        }
+    }
+
+    /// take the time since the last update off whoever was running
+    pub fn charge(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.last_update);
+        self.last_update = now;
+        if let Some(id) = self.running {
+            let remaining = self.remaining_ms.entry(id).or_insert(self.initial_ms);
+            *remaining = remaining.saturating_sub(as_ms(elapsed));
+        }
+    }
+
+    /// start the clock of whoever has to move next, `None` stops them all.
+    /// everyone seated gets a full clock the first time they are seen
+    pub fn switch_to(&mut self, seats: &[Option<usize>], running: Option<usize>, now: Instant) {
+        self.charge(now);
+        for id in seats.iter().flatten() {
+            self.remaining_ms.entry(*id).or_insert(self.initial_ms);
+        }
+        self.running = running;
+    }
+
+    /// `player_id` just moved, charge them and give them their increment
+    pub fn moved(&mut self, player_id: usize, now: Instant) {
+        self.charge(now);
+        let increment = self.increment_ms;
+        let initial = self.initial_ms;
+        *self.remaining_ms.entry(player_id).or_insert(initial) += increment;
+    }
+
+    /// the running player, if they are out of time
+    pub fn flagged(&self) -> Option<usize> {
+        let id = self.running?;
+        match self.remaining_ms.get(&id) {
+            Some(0) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// put everyone back to their starting time for a new game
+    pub fn reset(&mut self, now: Instant) {
+        for remaining in self.remaining_ms.values_mut() {
+            *remaining = self.initial_ms;
+        }
+        self.running = None;
+        self.last_update = now;
+    }
+}
+
+fn as_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(initial_seconds: u64, increment_seconds: u64, start: Instant) -> GameClock {
+        let mut clock = GameClock::new(
+            &ClockOptions {
+                initial_seconds,
+                increment_seconds,
+            },
+            start,
+        );
+        clock.switch_to(&[Some(1), Some(2)], Some(1), start);
+        clock
+    }
+
+    #[test]
+    fn only_the_running_clock_goes_down() {
+        let start = Instant::now();
+        let mut c = clock(60, 0, start);
+        c.moved(1, start + Duration::from_secs(10));
+        c.switch_to(
+            &[Some(1), Some(2)],
+            Some(2),
+            start + Duration::from_secs(10),
+        );
+        c.charge(start + Duration::from_secs(15));
+        assert_eq!(c.remaining_ms[&1], 50_000);
+        assert_eq!(c.remaining_ms[&2], 55_000);
+    }
+
+    #[test]
+    fn fischer_increment_is_added_after_a_move() {
+        let start = Instant::now();
+        let mut c = clock(60, 2, start);
+        c.moved(1, start + Duration::from_secs(5));
+        assert_eq!(c.remaining_ms[&1], 57_000);
+    }
+
+    #[test]
+    fn running_out_of_time_flags() {
+        let start = Instant::now();
+        let mut c = clock(3, 0, start);
+        c.charge(start + Duration::from_secs(2));
+        assert_eq!(c.flagged(), None);
+        c.charge(start + Duration::from_secs(4));
+        assert_eq!(c.flagged(), Some(1));
+        assert_eq!(c.remaining_ms[&1], 0);
+    }
+}
@@ -0,0 +1,208 @@
+//! Connect Four: tic-tac-toe with gravity. A move only names a column,
+//! `[x]`, and the piece falls to the lowest empty square in it. Standard
+//! boards are 7 wide and 6 high with four in a row winning.
+
+use bitboard::{check_size, win_masks, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConnectFourGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    pub width: usize,
+    pub height: usize,
+    /// how many in a row it takes to win
+    pub win_length: usize,
+    /// the pieces, rows from the top like every other board, so pieces
+    /// stack up from the last row
+    pub grid: Board,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far as the column played, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl ConnectFourGame {
+    /// a `width` by `height` board where `win_length` in a row wins
+    pub fn with_size(width: usize, height: usize, win_length: usize) -> Result<Self, String> {
+        check_size(width, height, win_length)?;
+        Ok(ConnectFourGame {
+            seating: Seating::default(),
+            width,
+            height,
+            win_length,
+            grid: Board::new(width, height),
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        })
+    }
+
+    /// the row a piece dropped in column `x` lands on, `None` once it's full
+    fn landing_row(&self, x: usize) -> Option<usize> {
+        (0..self.height)
+            .rev()
+            .find(|&y| self.grid.get(x, y) == Cell::Empty)
+    }
+
+    /// the seat of `player_id` and the square their piece lands on
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let x = match turn.position.as_slice() {
+            [x] => *x,
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= self.width {
+            return Err(MoveError::OutOfBounds);
+        }
+        let y = self.landing_row(x).ok_or(MoveError::Occupied)?;
+        Ok((seat, x, y))
+    }
+
+    fn get_outcome(&self) -> GameOutcome {
+        let masks = win_masks(self.width, self.height, self.win_length);
+        let winner = self
+            .grid
+            .completed_line(&masks)
+            .and_then(|(seat, line)| Some((self.seating.player_in(seat)?, line)));
+        if let Some((player, line)) = winner {
+            return GameOutcome::Won {
+                player,
+                cells: line
+                    .squares()
+                    .into_iter()
+                    .map(|(x, y)| vec![x, y])
+                    .collect(),
+            };
+        }
+        if self.grid.is_full() {
+            GameOutcome::Draw
+        } else {
+            GameOutcome::InProgress
+        }
+    }
+}
+
+impl Game for ConnectFourGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, x, y) = self.check_move(player_id, turn)?;
+        self.grid.set(x, y, Cell::Taken(seat));
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        self.outcome = self.get_outcome();
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        (0..self.width)
+            .filter(|&x| self.landing_row(x).is_some())
+            .map(|x| GameTurnMessage { position: vec![x] })
+            .collect()
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seated() -> ConnectFourGame {
+        let mut g = ConnectFourGame::with_size(7, 6, 4).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut ConnectFourGame, player_id: usize, x: usize) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position: vec![x] })
+    }
+
+    #[test]
+    fn pieces_fall_to_the_bottom() {
+        let mut g = seated();
+        play(&mut g, 1, 3).unwrap();
+        play(&mut g, 2, 3).unwrap();
+        assert_eq!(g.grid.get(3, 5), Cell::Taken(Seat::One));
+        assert_eq!(g.grid.get(3, 4), Cell::Taken(Seat::Two));
+        assert_eq!(play(&mut g, 1, 7), Err(MoveError::OutOfBounds));
+        assert_eq!(
+            g.apply_move(
+                1,
+                &GameTurnMessage {
+                    position: vec![3, 3],
+                },
+            ),
+            Err(MoveError::Malformed)
+        );
+    }
+
+    #[test]
+    fn full_columns_are_closed() {
+        let mut g = seated();
+        for i in 0..6 {
+            play(&mut g, 1 + i % 2, 0).unwrap();
+        }
+        assert_eq!(play(&mut g, 1, 0), Err(MoveError::Occupied));
+        assert_eq!(g.legal_moves().len(), 6);
+    }
+
+    #[test]
+    fn four_in_a_row_along_the_bottom_wins() {
+        let mut g = seated();
+        for x in 0..3 {
+            play(&mut g, 1, x).unwrap();
+            play(&mut g, 2, x).unwrap();
+        }
+        play(&mut g, 1, 3).unwrap();
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![0, 5], vec![1, 5], vec![2, 5], vec![3, 5]],
+            }
+        );
+        assert_eq!(g.current_player(), None);
+    }
+}
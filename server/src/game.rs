@@ -0,0 +1,429 @@
+//! The rules every game a `ChatRoom` can host has to follow. The room only
+//! ever talks to its game through the `Game` trait, so a new variant is a new
+//! implementation rather than a copy of the room plumbing.
+
+use record::GameRecord;
+use serde::de::{self, Unexpected};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// a move as the client sends it in the data of a "move" event
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameTurnMessage {
+    pub position: Vec<usize>,
+}
+
+/// one entry in a game's move list
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PlayedMove {
+    pub player: usize,
+    #[serde(flatten)]
+    pub turn: GameTurnMessage,
+}
+
+/// one side of a two player game, `One` plays x and moves first. sent as
+/// the seat number, 1 or 2
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Seat {
+    #[default]
+    One,
+    Two,
+}
+
+impl Seat {
+    /// the seat numbered `number` on the wire, `None` if there is no such seat
+    pub fn from_number(number: u64) -> Option<Seat> {
+        match number {
+            1 => Some(Seat::One),
+            2 => Some(Seat::Two),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u8 {
+        match self {
+            Seat::One => 1,
+            Seat::Two => 2,
+        }
+    }
+
+    /// whoever moves after this seat
+    pub fn next(self) -> Seat {
+        match self {
+            Seat::One => Seat::Two,
+            Seat::Two => Seat::One,
+        }
+    }
+
+    /// the mark this seat leaves on the board, in positions and records
+    pub fn symbol(self) -> char {
+        match self {
+            Seat::One => 'x',
+            Seat::Two => 'o',
+        }
+    }
+}
+
+impl Serialize for Seat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(self.number())
+    }
+}
+
+impl<'de> Deserialize<'de> for Seat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let number = u64::deserialize(deserializer)?;
+        Seat::from_number(number)
+            .ok_or_else(|| de::Error::invalid_value(Unexpected::Unsigned(number), &"1 or 2"))
+    }
+}
+
+/// a square on the board, sent as 0 when empty and the seat number otherwise
+/// so clients keep getting the plain numbers they always have, and as 3 when
+/// it's blocked
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Cell {
+    #[default]
+    Empty,
+    Taken(Seat),
+    /// taken out of play before the game started, nobody can mark it
+    Blocked,
+}
+
+/// what a blocked cell goes over the wire as
+const BLOCKED: u64 = 3;
+
+impl Serialize for Cell {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Cell::Empty => serializer.serialize_u8(0),
+            Cell::Taken(seat) => seat.serialize(serializer),
+            Cell::Blocked => serializer.serialize_u64(BLOCKED),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Cell {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match u64::deserialize(deserializer)? {
+            0 => Ok(Cell::Empty),
+            BLOCKED => Ok(Cell::Blocked),
+            number => Seat::from_number(number).map(Cell::Taken).ok_or_else(|| {
+                de::Error::invalid_value(Unexpected::Unsigned(number), &"0, 1, 2 or 3")
+            }),
+        }
+    }
+}
+
+/// where a game stands, sent to clients under `outcome` with a `status` tag
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum GameOutcome {
+    #[default]
+    InProgress,
+    /// `cells` is the line that decided the game, empty after a forfeit.
+    /// how each cell is written depends on the variant:
+    ///
+    /// * `[x, y]` squares on the board in tic-tac-toe, connect four, order
+    ///   and chaos, numerical, quantum, gomoku and games for three or more
+    /// * `[x, y]` small boards, not squares, in ultimate tic-tac-toe
+    /// * `[x, y, z]` in qubic
+    /// * `[board, x, y]` in notakto, the line that killed the last board
+    Won {
+        player: usize,
+        cells: Vec<Vec<usize>>,
+    },
+    Draw,
+}
+
+impl GameOutcome {
+    pub fn winner(&self) -> Option<usize> {
+        match self {
+            GameOutcome::Won { player, .. } => Some(*player),
+            _ => None,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        *self != GameOutcome::InProgress
+    }
+
+    /// how a game ends when someone gives up, `opponent` wins if there is one
+    pub fn forfeited_to(opponent: Option<usize>) -> GameOutcome {
+        match opponent {
+            Some(player) => GameOutcome::Won {
+                player,
+                cells: Vec::new(),
+            },
+            None => GameOutcome::Draw,
+        }
+    }
+}
+
+/// who sits where in a two player game and whose turn it is. games flatten
+/// it into their state, so clients see `player1`, `player2`,
+/// `current_player_turn` and `to_move` at the top level
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Seating {
+    pub player1: Option<usize>,
+    pub player2: Option<usize>,
+    pub current_player_turn: Option<usize>,
+    /// whose side moves next, kept while a seat is empty so whoever sits
+    /// down next picks up the right side
+    pub to_move: Seat,
+}
+
+impl Seating {
+    pub fn seats(&self) -> Vec<Option<usize>> {
+        vec![self.player1, self.player2]
+    }
+
+    /// sit `id` in the first empty seat, true once both seats are taken
+    pub fn add_player(&mut self, id: usize) -> bool {
+        if self.player1.is_none() {
+            self.player1 = Some(id);
+        } else if self.player2.is_none() {
+            self.player2 = Some(id);
+        }
+        self.player1.is_some() && self.player2.is_some()
+    }
+
+    /// sit `id` down for a game's `add_player`: the first move waits until
+    /// both seats are taken, and a game that is over stays over
+    pub fn join(&mut self, id: usize, outcome: &GameOutcome) {
+        if self.add_player(id) && !outcome.is_over() {
+            self.start_turn();
+        }
+    }
+
+    /// what `outcome` becomes when `player_id` gives up: a win for whoever
+    /// sits opposite, unless the game is over already. nobody moves after
+    pub fn forfeit(&mut self, player_id: usize, outcome: &GameOutcome) -> GameOutcome {
+        if outcome.is_over() {
+            return outcome.clone();
+        }
+        self.current_player_turn = None;
+        GameOutcome::forfeited_to(self.opponent(player_id))
+    }
+
+    /// empty `id`'s seat. nobody can move until it is filled again, and
+    /// `start_turn` picks the turn back up then
+    pub fn remove_player(&mut self, id: usize) {
+        if self.player1 == Some(id) {
+            self.player1 = None;
+        } else if self.player2 == Some(id) {
+            self.player2 = None;
+        } else {
+            return;
+        }
+        self.current_player_turn = None;
+    }
+
+    /// who is sitting in `seat`
+    pub fn player_in(&self, seat: Seat) -> Option<usize> {
+        match seat {
+            Seat::One => self.player1,
+            Seat::Two => self.player2,
+        }
+    }
+
+    /// where `player_id` is sitting, if they are playing at all
+    pub fn seat_of(&self, player_id: usize) -> Option<Seat> {
+        if self.player1 == Some(player_id) {
+            Some(Seat::One)
+        } else if self.player2 == Some(player_id) {
+            Some(Seat::Two)
+        } else {
+            None
+        }
+    }
+
+    /// whoever is left to win if `player_id` gives up
+    pub fn opponent(&self, player_id: usize) -> Option<usize> {
+        match self.seat_of(player_id) {
+            Some(seat) => self.player_in(seat.next()),
+            None => None,
+        }
+    }
+
+    /// give the turn to whoever sits on the side to move
+    pub fn start_turn(&mut self) {
+        self.current_player_turn = self.player_in(self.to_move);
+    }
+
+    /// `seat` just moved, so the other side is up
+    pub fn pass_turn(&mut self, seat: Seat) {
+        self.to_move = seat.next();
+        self.start_turn();
+    }
+
+    /// the players trade sides, whoever had x now has o and the other way
+    /// round. the side to move stays the same
+    pub fn swap_seats(&mut self) {
+        std::mem::swap(&mut self.player1, &mut self.player2);
+        self.start_turn();
+    }
+
+    /// the seat of `player_id` if both seats are taken and it is their turn
+    pub fn check_turn(&self, player_id: usize) -> Result<Seat, MoveError> {
+        match (self.player1, self.player2, self.current_player_turn) {
+            (Some(_), Some(_), Some(current)) if current == player_id => {
+                self.seat_of(player_id).ok_or(MoveError::NotYourTurn)
+            }
+            (Some(_), Some(_), Some(_)) => Err(MoveError::NotYourTurn),
+            _ => Err(MoveError::GameNotStarted),
+        }
+    }
+}
+
+/// why a move was refused, sent back to the player who tried it
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MoveError {
+    Occupied,
+    OutOfBounds,
+    NotYourTurn,
+    GameOver,
+    GameNotStarted,
+    Malformed,
+    SeatsFull,
+    NothingToTakeBack,
+    NoRequestToAnswer,
+    NotSeated,
+    GameInProgress,
+    NotRoomOwner,
+    WrongBoard,
+    CantSwap,
+    NumberNotYours,
+    DeadBoard,
+    CollapsePending,
+    BadCollapse,
+    Forbidden,
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            MoveError::Occupied => "that cell is already taken",
+            MoveError::OutOfBounds => "that cell is not on the board",
+            MoveError::NotYourTurn => "it is not your turn",
+            MoveError::GameOver => "the game is already over",
+            MoveError::GameNotStarted => "the game needs all its players before anyone can move",
+            MoveError::Malformed => "could not understand that move",
+            MoveError::SeatsFull => "every seat in this game is taken",
+            MoveError::NothingToTakeBack => "you have no move to take back",
+            MoveError::NoRequestToAnswer => "your opponent has not asked for anything",
+            MoveError::NotSeated => "you are not playing in this game",
+            MoveError::GameInProgress => "finish this game first",
+            MoveError::NotRoomOwner => "only whoever created the room can do that",
+            MoveError::WrongBoard => "you have to play in the board you were sent to",
+            MoveError::CantSwap => "sides can only be swapped in reply to the first move",
+            MoveError::NumberNotYours => "that number is not one you have left to play",
+            MoveError::DeadBoard => "that board already has three in a row",
+            MoveError::CollapsePending => "the last cycle has to be collapsed first",
+            MoveError::BadCollapse => "the mark can only collapse into one of its two squares",
+            MoveError::Forbidden => "renju rules forbid black from playing there",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+/// what every game gets from being `Clone` and `Serialize`, without
+/// writing it out again for each variant
+pub trait GameSnapshot {
+    /// what goes in the `content` of a "board" event
+    fn state(&self) -> serde_json::Value;
+
+    fn box_clone(&self) -> Box<dyn Game>;
+}
+
+impl<T: Game + Clone + Serialize + 'static> GameSnapshot for T {
+    fn state(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("a game always serializes")
+    }
+
+    fn box_clone(&self) -> Box<dyn Game> {
+        Box::new(self.clone())
+    }
+}
+
+/// a game a room can be created with. games are `Send` so computer players
+/// can think about them on their own threads
+pub trait Game: GameSnapshot + Send {
+    /// the player in each seat, in turn order
+    fn seats(&self) -> Vec<Option<usize>>;
+
+    /// how many players have to be seated before anyone can move
+    fn seat_count(&self) -> usize {
+        self.seats().len()
+    }
+
+    /// sit the player in the first empty seat, if there is one
+    fn add_player(&mut self, id: usize);
+
+    fn remove_player(&mut self, id: usize);
+
+    /// whose turn it is, `None` before the game starts and after it ends
+    fn current_player(&self) -> Option<usize>;
+
+    /// play a move for `player_id`, leaving the game untouched if it is refused
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError>;
+
+    /// every move the current player could make right now
+    fn legal_moves(&self) -> Vec<GameTurnMessage>;
+
+    /// the moves a search should try from here. `legal_moves` unless a game
+    /// can list a few more much more cheaply, which `apply_move` then refuses,
+    /// or knows most of them aren't worth trying
+    fn candidate_moves(&self) -> Vec<GameTurnMessage> {
+        self.legal_moves()
+    }
+
+    fn outcome(&self) -> GameOutcome;
+
+    /// end the game with `player_id` losing, e.g. when their clock runs out
+    fn forfeit(&mut self, player_id: usize);
+
+    /// the board as a one line position string, for logs and share links
+    fn position(&self) -> Option<String> {
+        None
+    }
+
+    /// the game written down for download, if the variant has a notation
+    fn record(&self) -> Option<GameRecord> {
+        None
+    }
+}
+
+impl Clone for Box<dyn Game> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_keep_their_numbers_on_the_wire() {
+        let row = vec![
+            Cell::Empty,
+            Cell::Taken(Seat::One),
+            Cell::Taken(Seat::Two),
+            Cell::Blocked,
+        ];
+        let json = serde_json::to_string(&row).unwrap();
+        assert_eq!(json, "[0,1,2,3]");
+        let back: Vec<Cell> = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, row);
+    }
+
+    #[test]
+    fn unknown_cells_and_seats_are_refused() {
+        assert!(serde_json::from_str::<Cell>("4").is_err());
+        assert!(serde_json::from_str::<Cell>("-1").is_err());
+        assert!(serde_json::from_str::<Seat>("0").is_err());
+        assert_eq!(serde_json::from_str::<Seat>("2").unwrap(), Seat::Two);
+    }
+}
@@ -0,0 +1,660 @@
+//! Gomoku on a 15x15 board, five in a row wins. Seat 1 plays black, `x`,
+//! and moves first. On top of free-style rules a room can ask for
+//!
+//! * `exact_five`, where six or more in a row don't count for anyone
+//! * `renju`, where black may not make an overline, a double four or a
+//!   double three, and only exactly five wins for black. White still wins
+//!   with an overline
+//! * `swap2`, the opening protocol: the first player places two black
+//!   stones and a white one, then the second player either plays white,
+//!   takes black, or places one more stone of each and lets the first
+//!   player pick
+//!
+//! Moves are `[x, y]`. During a swap2 opening `[]` takes black and lets
+//! whoever is white move, and the second player's two extra stones are
+//! `[x1, y1, x2, y2]`, white first.
+
+use bitboard::{Bitboard, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+const SIDE: usize = 15;
+
+/// how many in a row win
+const FIVE: usize = 5;
+
+/// how far from the stones already down searches look for moves
+const NEAR: isize = 2;
+
+/// one way along every line, the other way is covered by walking back
+const DIRECTIONS: [(isize, isize); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// the rule options, all off for free-style gomoku
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GomokuRules {
+    /// six or more in a row don't win
+    pub exact_five: bool,
+    /// black may not make overlines, double fours or double threes
+    pub renju: bool,
+    /// open with swap2 instead of black simply moving first
+    pub swap2: bool,
+}
+
+/// how far into a swap2 opening the game is
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum Opening {
+    /// the first player is placing black, white, black
+    PlaceThree { placed: usize },
+    /// the second player plays white `[x, y]`, takes black `[]` or places
+    /// two more stones `[x1, y1, x2, y2]`
+    ChooseSide,
+    /// the first player takes black `[]` or white by playing it `[x, y]`
+    ChooseColor,
+}
+
+/// what a move that passed `check_move` does
+enum Play {
+    /// put a stone of this colour on (x, y)
+    Stone(Seat, usize, usize),
+    /// take black, handing the move to white
+    TakeBlack,
+    /// a white stone then a black one, leaving the choice to the first player
+    TwoMore((usize, usize), (usize, usize)),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GomokuGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// the stones, 1 for black and 2 for white
+    pub grid: Board,
+    pub rules: GomokuRules,
+    /// where a swap2 opening stands, `None` once it's done or without one
+    pub opening: Option<Opening>,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl GomokuGame {
+    pub fn new(rules: GomokuRules) -> Self {
+        GomokuGame {
+            seating: Seating::default(),
+            grid: Board::new(SIDE, SIDE),
+            rules,
+            opening: if rules.swap2 {
+                Some(Opening::PlaceThree { placed: 0 })
+            } else {
+                None
+            },
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        }
+    }
+
+    /// every move but the second player's two stone offers in swap2, there
+    /// are tens of thousands of those. black's renju-forbidden squares are
+    /// left out when `check_renju` is set
+    fn moves(&self, squares: Bitboard, check_renju: bool) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        if let Some(Opening::ChooseSide) | Some(Opening::ChooseColor) = self.opening {
+            moves.push(GameTurnMessage {
+                position: Vec::new(),
+            });
+        }
+        let check = check_renju
+            && self.rules.renju
+            && self.opening.is_none()
+            && self.seating.to_move == Seat::One;
+        for (x, y) in squares.squares() {
+            if check && is_forbidden(&self.grid, x, y) {
+                continue;
+            }
+            moves.push(GameTurnMessage {
+                position: vec![x, y],
+            });
+        }
+        moves
+    }
+
+    /// the empty squares within `NEAR` of a stone, the centre on an empty
+    /// board, or every empty square if none of those are left
+    fn near_stones(&self) -> Bitboard {
+        let empty = self.grid.empty();
+        let stones = self.grid.marks(Seat::One).union(self.grid.marks(Seat::Two));
+        let mut near = Bitboard::default();
+        if stones.is_empty() {
+            near.insert(SIDE / 2, SIDE / 2);
+        }
+        for (x, y) in stones.squares() {
+            for dy in -NEAR..=NEAR {
+                for dx in -NEAR..=NEAR {
+                    if let Some((sx, sy)) = step(x, y, dx, dy, 1) {
+                        near.insert(sx, sy);
+                    }
+                }
+            }
+        }
+        let near = empty.difference(empty.difference(near));
+        if near.is_empty() {
+            empty
+        } else {
+            near
+        }
+    }
+
+    /// the empty square at `x, y`, if it is one
+    fn empty_square(&self, x: usize, y: usize) -> Result<(usize, usize), MoveError> {
+        if x >= SIDE || y >= SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.grid.get(x, y) != Cell::Empty {
+            return Err(MoveError::Occupied);
+        }
+        Ok((x, y))
+    }
+
+    /// the seat of `player_id` and what their move does, if they may play it
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, Play), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let play = match (self.opening, turn.position.as_slice()) {
+            (Some(Opening::PlaceThree { placed }), [x, y]) => {
+                // black, white, black
+                let colour = if placed == 1 { Seat::Two } else { Seat::One };
+                let (x, y) = self.empty_square(*x, *y)?;
+                Play::Stone(colour, x, y)
+            }
+            (Some(Opening::ChooseSide), [x1, y1, x2, y2]) => {
+                let white = self.empty_square(*x1, *y1)?;
+                let black = self.empty_square(*x2, *y2)?;
+                if white == black {
+                    return Err(MoveError::Occupied);
+                }
+                Play::TwoMore(white, black)
+            }
+            (Some(Opening::ChooseSide), []) | (Some(Opening::ChooseColor), []) => Play::TakeBlack,
+            (Some(Opening::ChooseSide), [x, y]) | (Some(Opening::ChooseColor), [x, y]) => {
+                let (x, y) = self.empty_square(*x, *y)?;
+                Play::Stone(Seat::Two, x, y)
+            }
+            (None, [x, y]) => {
+                let (x, y) = self.empty_square(*x, *y)?;
+                if seat == Seat::One && self.rules.renju && is_forbidden(&self.grid, x, y) {
+                    return Err(MoveError::Forbidden);
+                }
+                Play::Stone(seat, x, y)
+            }
+            _ => return Err(MoveError::Malformed),
+        };
+        Ok((seat, play))
+    }
+
+    /// the run of `colour` through the stone just put on (x, y) that wins,
+    /// if there is one
+    fn winning_run(&self, colour: Seat, x: usize, y: usize) -> Option<Vec<(usize, usize)>> {
+        // overlines only count for white under renju, and not at all with
+        // exact five
+        let overline_wins = !(self.rules.exact_five || self.rules.renju && colour == Seat::One);
+        DIRECTIONS
+            .iter()
+            .map(|&(dx, dy)| run(&self.grid, colour, x, y, dx, dy))
+            .find(|squares| squares.len() == FIVE || (squares.len() > FIVE && overline_wins))
+    }
+}
+
+/// the square `steps` along (dx, dy) from (x, y), if it's on the board
+fn step(x: usize, y: usize, dx: isize, dy: isize, steps: isize) -> Option<(usize, usize)> {
+    let (x, y) = (x as isize + dx * steps, y as isize + dy * steps);
+    if x < 0 || y < 0 || x >= SIDE as isize || y >= SIDE as isize {
+        None
+    } else {
+        Some((x as usize, y as usize))
+    }
+}
+
+/// the unbroken line of `colour` through (x, y) along (dx, dy)
+fn run(
+    grid: &Board,
+    colour: Seat,
+    x: usize,
+    y: usize,
+    dx: isize,
+    dy: isize,
+) -> Vec<(usize, usize)> {
+    let is_colour = |square: &(usize, usize)| grid.get(square.0, square.1) == Cell::Taken(colour);
+    let mut squares: Vec<(usize, usize)> = (1..)
+        .map(|i| step(x, y, -dx, -dy, i))
+        .take_while(|square| square.iter().any(is_colour))
+        .flatten()
+        .collect();
+    squares.reverse();
+    squares.push((x, y));
+    squares.extend(
+        (1..)
+            .map(|i| step(x, y, dx, dy, i))
+            .take_while(|square| square.iter().any(is_colour))
+            .flatten(),
+    );
+    squares
+}
+
+/// the empty squares along (dx, dy) that would give black exactly five
+/// through (x, y)
+fn five_spots(grid: &Board, x: usize, y: usize, dx: isize, dy: isize) -> Vec<(usize, usize)> {
+    let mut spots = Vec::new();
+    for i in -(FIVE as isize - 1)..FIVE as isize {
+        let (sx, sy) = match step(x, y, dx, dy, i) {
+            Some(square) => square,
+            None => continue,
+        };
+        if grid.get(sx, sy) != Cell::Empty {
+            continue;
+        }
+        let mut after = grid.clone();
+        after.set(sx, sy, Cell::Taken(Seat::One));
+        if run(&after, Seat::One, x, y, dx, dy).len() == FIVE {
+            spots.push((sx, sy));
+        }
+    }
+    spots
+}
+
+/// whether the ways to make five are the two ends of an open four, four in
+/// a row with both sides empty
+fn is_open_four(spots: &[(usize, usize)]) -> bool {
+    match spots {
+        [(x1, y1), (x2, y2)] => x1.max(x2) - x1.min(x2) == FIVE || y1.max(y2) - y1.min(y2) == FIVE,
+        _ => false,
+    }
+}
+
+/// how many fours black has through (x, y) along (dx, dy). an open four
+/// has two ways to make five but is still one four
+fn fours(grid: &Board, x: usize, y: usize, dx: isize, dy: isize) -> usize {
+    let spots = five_spots(grid, x, y, dx, dy);
+    if is_open_four(&spots) {
+        1
+    } else {
+        spots.len()
+    }
+}
+
+/// whether black has a three through (x, y) along (dx, dy), a line one
+/// stone away from an open four. the stone that makes the four has to be
+/// one black may play, so a three whose only way on is forbidden doesn't
+/// count. each check looks at a board with one more stone on it, so this
+/// always bottoms out
+fn is_three(grid: &Board, x: usize, y: usize, dx: isize, dy: isize) -> bool {
+    (-(FIVE as isize - 1)..FIVE as isize).any(|i| {
+        let (sx, sy) = match step(x, y, dx, dy, i) {
+            Some(square) => square,
+            None => return false,
+        };
+        if grid.get(sx, sy) != Cell::Empty {
+            return false;
+        }
+        let mut after = grid.clone();
+        after.set(sx, sy, Cell::Taken(Seat::One));
+        is_open_four(&five_spots(&after, x, y, dx, dy)) && !is_forbidden(grid, sx, sy)
+    })
+}
+
+/// whether renju forbids black from playing (x, y). making five always wins,
+/// otherwise an overline, two fours or two threes are forbidden
+fn is_forbidden(grid: &Board, x: usize, y: usize) -> bool {
+    // even the smallest forbidden shape, a double three, lines (x, y) up
+    // with four other black stones, so most squares are quickly cleared
+    let reach = FIVE as isize - 1;
+    let in_line = DIRECTIONS
+        .iter()
+        .flat_map(|&(dx, dy)| (-reach..=reach).filter_map(move |i| step(x, y, dx, dy, i)))
+        .filter(|&(sx, sy)| grid.get(sx, sy) == Cell::Taken(Seat::One))
+        .count();
+    if in_line < 4 {
+        return false;
+    }
+    let mut after = grid.clone();
+    after.set(x, y, Cell::Taken(Seat::One));
+    let runs: Vec<usize> = DIRECTIONS
+        .iter()
+        .map(|&(dx, dy)| run(&after, Seat::One, x, y, dx, dy).len())
+        .collect();
+    if runs.contains(&FIVE) {
+        return false;
+    }
+    if runs.iter().any(|&length| length > FIVE) {
+        return true;
+    }
+    let mut four_count = 0;
+    let mut three_count = 0;
+    for &(dx, dy) in DIRECTIONS.iter() {
+        let found = fours(&after, x, y, dx, dy);
+        if found > 0 {
+            four_count += found;
+        } else if is_three(&after, x, y, dx, dy) {
+            three_count += 1;
+        }
+    }
+    four_count >= 2 || three_count >= 2
+}
+
+impl Game for GomokuGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, play) = self.check_move(player_id, turn)?;
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        let (colour, x, y) = match (self.opening, play) {
+            (Some(Opening::PlaceThree { placed }), Play::Stone(colour, x, y)) => {
+                self.grid.set(x, y, Cell::Taken(colour));
+                self.opening = if placed == 2 {
+                    self.seating.pass_turn(seat);
+                    Some(Opening::ChooseSide)
+                } else {
+                    Some(Opening::PlaceThree { placed: placed + 1 })
+                };
+                return Ok(());
+            }
+            (_, Play::TwoMore((wx, wy), (bx, by))) => {
+                self.grid.set(wx, wy, Cell::Taken(Seat::Two));
+                self.grid.set(bx, by, Cell::Taken(Seat::One));
+                self.opening = Some(Opening::ChooseColor);
+                self.seating.pass_turn(seat);
+                return Ok(());
+            }
+            (_, Play::TakeBlack) => {
+                // whoever takes black ends up in seat 1, then white moves
+                self.opening = None;
+                if seat == Seat::Two {
+                    self.seating.swap_seats();
+                } else {
+                    self.seating.pass_turn(seat);
+                }
+                return Ok(());
+            }
+            (Some(_), Play::Stone(colour, x, y)) => {
+                // taking white by playing it
+                self.opening = None;
+                if seat == Seat::One {
+                    self.seating.swap_seats();
+                }
+                (colour, x, y)
+            }
+            (None, Play::Stone(colour, x, y)) => (colour, x, y),
+        };
+        self.grid.set(x, y, Cell::Taken(colour));
+        self.seating.pass_turn(colour);
+        let winner = self
+            .winning_run(colour, x, y)
+            .and_then(|line| Some((self.seating.player_in(colour)?, line)));
+        self.outcome = match winner {
+            Some((player, line)) => GameOutcome::Won {
+                player,
+                cells: line.into_iter().map(|(x, y)| vec![x, y]).collect(),
+            },
+            None if self.grid.is_full() => GameOutcome::Draw,
+            None => GameOutcome::InProgress,
+        };
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        self.moves(self.grid.empty(), true)
+    }
+
+    /// stones far from the rest hardly ever matter, so searches only try
+    /// squares near them. checking every square for renju fouls costs more
+    /// than the rest of a playout, so they let `apply_move` refuse fouls
+    fn candidate_moves(&self) -> Vec<GameTurnMessage> {
+        self.moves(self.near_stones(), false)
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated(rules: GomokuRules) -> GomokuGame {
+        let mut g = GomokuGame::new(rules);
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut GomokuGame, player_id: usize, position: Vec<usize>) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    /// put stones straight on the board, `x` for black and `o` for white
+    fn stones(g: &mut GomokuGame, placed: &[(usize, usize, char)]) {
+        for &(x, y, stone) in placed {
+            let colour = if stone == 'x' { Seat::One } else { Seat::Two };
+            g.grid.set(x, y, Cell::Taken(colour));
+        }
+    }
+
+    fn renju() -> GomokuRules {
+        GomokuRules {
+            renju: true,
+            ..GomokuRules::default()
+        }
+    }
+
+    #[test]
+    fn five_in_a_row_wins() {
+        let mut g = seated(GomokuRules::default());
+        for x in 0..4 {
+            play(&mut g, 1, vec![x, 7]).unwrap();
+            play(&mut g, 2, vec![x, 8]).unwrap();
+        }
+        play(&mut g, 1, vec![4, 7]).unwrap();
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: (0..5).map(|x| vec![x, 7]).collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn overlines_only_win_in_free_style() {
+        let line = [
+            (0, 0, 'x'),
+            (1, 0, 'x'),
+            (2, 0, 'x'),
+            (4, 0, 'x'),
+            (5, 0, 'x'),
+        ];
+        let mut g = seated(GomokuRules::default());
+        stones(&mut g, &line);
+        play(&mut g, 1, vec![3, 0]).unwrap();
+        assert_eq!(g.winner, Some(1));
+
+        let mut g = seated(GomokuRules {
+            exact_five: true,
+            ..GomokuRules::default()
+        });
+        stones(&mut g, &line);
+        play(&mut g, 1, vec![3, 0]).unwrap();
+        assert_eq!(g.outcome, GameOutcome::InProgress);
+    }
+
+    #[test]
+    fn renju_forbids_black_overlines_and_doubles() {
+        // an overline
+        let mut g = seated(renju());
+        stones(
+            &mut g,
+            &[
+                (0, 0, 'x'),
+                (1, 0, 'x'),
+                (2, 0, 'x'),
+                (4, 0, 'x'),
+                (5, 0, 'x'),
+            ],
+        );
+        assert_eq!(play(&mut g, 1, vec![3, 0]), Err(MoveError::Forbidden));
+
+        // a double three, open twos crossing at (7, 7)
+        let mut g = seated(renju());
+        stones(
+            &mut g,
+            &[(5, 7, 'x'), (6, 7, 'x'), (7, 5, 'x'), (7, 6, 'x')],
+        );
+        assert_eq!(play(&mut g, 1, vec![7, 7]), Err(MoveError::Forbidden));
+        let foul = GameTurnMessage {
+            position: vec![7, 7],
+        };
+        assert!(!g.legal_moves().contains(&foul));
+        assert!(g.candidate_moves().contains(&foul));
+
+        // a double four, threes crossing at (7, 7) even with closed ends
+        let mut g = seated(renju());
+        stones(
+            &mut g,
+            &[
+                (4, 7, 'x'),
+                (5, 7, 'x'),
+                (6, 7, 'x'),
+                (3, 7, 'o'),
+                (7, 4, 'x'),
+                (7, 5, 'x'),
+                (7, 6, 'x'),
+                (7, 3, 'o'),
+            ],
+        );
+        assert_eq!(play(&mut g, 1, vec![7, 7]), Err(MoveError::Forbidden));
+
+        // a single three and a four at once is fine
+        let mut g = seated(renju());
+        stones(
+            &mut g,
+            &[
+                (4, 7, 'x'),
+                (5, 7, 'x'),
+                (6, 7, 'x'),
+                (3, 7, 'o'),
+                (7, 5, 'x'),
+                (7, 6, 'x'),
+            ],
+        );
+        play(&mut g, 1, vec![7, 7]).unwrap();
+
+        // the row only opens up at (8, 7), which would be a double four,
+        // so it isn't a three and crossing it with one is fine
+        let mut g = seated(renju());
+        stones(
+            &mut g,
+            &[
+                (5, 7, 'x'),
+                (6, 7, 'x'),
+                (3, 7, 'o'),
+                (7, 5, 'x'),
+                (7, 6, 'x'),
+                (8, 9, 'x'),
+                (8, 10, 'x'),
+                (8, 11, 'x'),
+            ],
+        );
+        play(&mut g, 1, vec![7, 7]).unwrap();
+
+        // and white may do all of it
+        let mut g = seated(renju());
+        play(&mut g, 1, vec![14, 14]).unwrap();
+        stones(
+            &mut g,
+            &[(5, 7, 'o'), (6, 7, 'o'), (7, 5, 'o'), (7, 6, 'o')],
+        );
+        play(&mut g, 2, vec![7, 7]).unwrap();
+    }
+
+    #[test]
+    fn swap2_second_player_can_take_black() {
+        let mut g = seated(GomokuRules {
+            swap2: true,
+            ..GomokuRules::default()
+        });
+        for &(x, y) in &[(7, 7), (8, 8), (7, 8)] {
+            play(&mut g, 1, vec![x, y]).unwrap();
+        }
+        assert_eq!(g.grid.get(8, 8), Cell::Taken(Seat::Two));
+        assert_eq!(g.opening, Some(Opening::ChooseSide));
+        assert_eq!(g.current_player(), Some(2));
+        play(&mut g, 2, Vec::new()).unwrap();
+        // player 2 is black now, so player 1 plays white
+        assert_eq!(g.seating.player1, Some(2));
+        assert_eq!(g.current_player(), Some(1));
+        play(&mut g, 1, vec![0, 0]).unwrap();
+        assert_eq!(g.grid.get(0, 0), Cell::Taken(Seat::Two));
+        assert_eq!(g.current_player(), Some(2));
+    }
+
+    #[test]
+    fn swap2_two_more_stones_hand_the_choice_back() {
+        let mut g = seated(GomokuRules {
+            swap2: true,
+            ..GomokuRules::default()
+        });
+        for &(x, y) in &[(7, 7), (8, 8), (7, 8)] {
+            play(&mut g, 1, vec![x, y]).unwrap();
+        }
+        play(&mut g, 2, vec![6, 6, 9, 9]).unwrap();
+        assert_eq!(g.grid.get(6, 6), Cell::Taken(Seat::Two));
+        assert_eq!(g.grid.get(9, 9), Cell::Taken(Seat::One));
+        assert_eq!(g.opening, Some(Opening::ChooseColor));
+        // player 1 takes white by playing a white stone
+        play(&mut g, 1, vec![5, 5]).unwrap();
+        assert_eq!(g.grid.get(5, 5), Cell::Taken(Seat::Two));
+        assert_eq!(g.seating.player2, Some(1));
+        assert_eq!(g.opening, None);
+        assert_eq!(g.current_player(), Some(2));
+        assert_eq!(g.state()["opening"], serde_json::Value::Null);
+    }
+}
@@ -20,9 +20,27 @@ use actix::{
     Running, StreamHandler, WrapFuture,
 };
 use actix_web::server::HttpServer;
-use actix_web::{fs, http, ws, App, Error, HttpRequest, HttpResponse};
-
+use actix_web::{
+    fs, http, ws, App, AsyncResponder, Error, FutureResponse, HttpRequest, HttpResponse,
+};
+use futures::Future;
+
+mod ai;
+mod bitboard;
+mod clock;
+mod connect_four;
+mod game;
+mod gomoku;
+mod multiplayer;
+mod notakto;
+mod numerical;
+mod order_chaos;
+mod quantum;
+mod qubic;
+mod record;
 mod server;
+mod tictactoe;
+mod ultimate;
 
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
@@ -47,6 +65,27 @@ fn chat_route(req: &HttpRequest<WsChatSessionState>) -> Result<HttpResponse, Err
     )
 }
 
+/// Download a room's current game as a text record
+fn record_route(req: &HttpRequest<WsChatSessionState>) -> FutureResponse<HttpResponse> {
+    let room = req.match_info().get("room").unwrap_or("Main").to_owned();
+    let filename = record::file_name(&room);
+    req.state()
+        .addr
+        .send(server::GetRecord { room })
+        .from_err()
+        .and_then(move |record| match record {
+            Some(text) => Ok(HttpResponse::Ok()
+                .content_type("text/plain; charset=utf-8")
+                .header(
+                    "Content-Disposition",
+                    format!("attachment; filename=\"{}\"", filename),
+                )
+                .body(text)),
+            None => Ok(HttpResponse::NotFound().finish()),
+        })
+        .responder()
+}
+
 struct WsChatSession {
     /// unique session id
     id: usize,
@@ -126,6 +165,18 @@ impl Handler<server::GameMessage> for WsChatSession {
                     }
                 }
             }
+            server::GameMessage::Error(error) => {
+                let f = serde_json::to_string(&error);
+                match f {
+                    Ok(json_string) => {
+                        println!("doing an error {}", &json_string);
+                        ctx.text(json_string);
+                    }
+                    Err(e) => {
+                        println!("error of {} trying to deal with {:?}", e, &error);
+                    }
+                }
+            }
         }
     }
 }
@@ -251,8 +302,40 @@ impl StreamHandler<ws::Message, ws::ProtocolError> for WsChatSession {
                             room: self.room.clone(),
                         });
                     }
-                    "move" => {
-                        println!("a game move");
+                    "join" => {
+                        println!("joining a room");
+                        let join: server::JoinRoomMessage =
+                            match serde_json::from_str(&general_message.data) {
+                                Ok(join) => join,
+                                Err(e) => {
+                                    eprintln!("bad join: {:?}", e);
+                                    ctx.text(format!("bad join: {:?}", e));
+                                    return;
+                                }
+                            };
+                        let room = join.room.clone();
+                        ctx.state()
+                            .addr
+                            .send(server::Join {
+                                id: self.id,
+                                name: join.room,
+                                options: join.options,
+                            })
+                            .into_actor(self)
+                            .then(move |res, act, ctx| {
+                                match res {
+                                    Ok(Ok(())) => act.room = room,
+                                    Ok(Err(e)) => ctx.text(format!("could not join: {}", e)),
+                                    // something is wrong with chat server
+                                    _ => ctx.stop(),
+                                }
+                                fut::ok(())
+                            })
+                            .wait(ctx);
+                    }
+                    "move" | "add_bot" | "takeback" | "takeback_accept" | "takeback_decline"
+                    | "rematch" | "rematch_accept" | "rematch_decline" | "set_position" => {
+                        println!("a game request {}", general_message.event_type);
                         ctx.state().addr.do_send(server::ClientMessage {
                             id: self.id,
                             event_type: general_message.event_type.clone(),
@@ -330,6 +413,10 @@ fn main() {
             })
             // websocket
             .resource("/ws/", |r| r.route().f(chat_route))
+            // game records
+            .resource("/rooms/{room}/record", |r| {
+                r.method(http::Method::GET).f(record_route)
+            })
             // static resources
             .handler("/static/", fs::StaticFiles::new("static/").unwrap())
     })
@@ -0,0 +1,432 @@
+//! Tic-tac-toe for three or more players on any board up to
+//! `MAX_BOARD_SIZE` a side, e.g. three players on 6x6 with four in a row
+//! winning. Seats move in order, each leaving its own symbol, and whoever
+//! completes a line first wins.
+//!
+//! Moves are `[x, y]`. Forfeiting, say by running out of time, only knocks
+//! that player out: their marks stay, their seat is skipped from then on, and
+//! the last player left wins. Someone leaving mid-game is skipped too, until
+//! somebody takes their seat.
+
+use bitboard::{check_size, win_masks, Bitboard};
+use game::{Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+use tictactoe::MAX_BOARD_SIZE;
+
+/// the symbol of each seat in turn order, so also the most players a game
+/// can have
+pub const SYMBOLS: [char; 6] = ['x', 'o', '+', '*', '@', '%'];
+
+/// the marks on a board, one bitboard per seat. it goes over the wire as
+/// rows of cells from the top, 0 when empty and the seat number of the mark
+/// otherwise
+#[derive(Clone, Debug, PartialEq)]
+pub struct Marks {
+    width: usize,
+    height: usize,
+    seats: [Bitboard; SYMBOLS.len()],
+}
+
+impl Marks {
+    pub fn new(width: usize, height: usize) -> Marks {
+        Marks {
+            width,
+            height,
+            seats: [Bitboard::default(); SYMBOLS.len()],
+        }
+    }
+
+    /// the number of the seat whose mark is on (x, y), 0 if nobody's
+    pub fn get(&self, x: usize, y: usize) -> usize {
+        self.seats
+            .iter()
+            .position(|marks| marks.contains(x, y))
+            .map_or(0, |i| i + 1)
+    }
+
+    /// put the mark of the seat numbered `seat` on (x, y)
+    pub fn set(&mut self, x: usize, y: usize, seat: usize) {
+        self.seats[seat - 1].insert(x, y);
+    }
+
+    /// the squares the seat numbered `seat` has marked
+    pub fn of(&self, seat: usize) -> Bitboard {
+        self.seats[seat - 1]
+    }
+
+    /// the squares nobody has marked yet
+    pub fn empty(&self) -> Bitboard {
+        self.seats
+            .iter()
+            .fold(Bitboard::full(self.width, self.height), |empty, &marks| {
+                empty.difference(marks)
+            })
+    }
+
+    pub fn rows(&self) -> Vec<Vec<usize>> {
+        (0..self.height)
+            .map(|y| (0..self.width).map(|x| self.get(x, y)).collect())
+            .collect()
+    }
+}
+
+impl Serialize for Marks {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.rows().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Marks {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let rows = Vec::<Vec<usize>>::deserialize(deserializer)?;
+        let height = rows.len();
+        let width = rows.first().map_or(0, |row| row.len());
+        if width > MAX_BOARD_SIZE || height > MAX_BOARD_SIZE {
+            return Err(de::Error::custom(format!(
+                "a board can't be bigger than {0}x{0}",
+                MAX_BOARD_SIZE
+            )));
+        }
+        if rows.iter().any(|row| row.len() != width) {
+            return Err(de::Error::custom(
+                "every row of a board has to be the same length",
+            ));
+        }
+        if rows.iter().flatten().any(|&seat| seat > SYMBOLS.len()) {
+            return Err(de::Error::custom(format!(
+                "a board has at most {} seats",
+                SYMBOLS.len()
+            )));
+        }
+        let mut marks = Marks::new(width, height);
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &seat) in row.iter().enumerate() {
+                if seat != 0 {
+                    marks.set(x, y, seat);
+                }
+            }
+        }
+        Ok(marks)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MultiplayerGame {
+    /// the player in each seat, in turn order
+    pub players: Vec<Option<usize>>,
+    /// the symbol of each seat, in the same order
+    pub symbols: Vec<char>,
+    pub current_player_turn: Option<usize>,
+    /// the number of the seat that moves next, 1 for the first
+    pub to_move: usize,
+    /// which seats have been knocked out
+    pub eliminated: Vec<bool>,
+    pub width: usize,
+    pub height: usize,
+    /// how many in a row it takes to win
+    pub win_length: usize,
+    /// the marks, rows of seat numbers on the wire
+    pub grid: Marks,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl MultiplayerGame {
+    /// a game for `players` on a `width` by `height` board where
+    /// `win_length` in a row wins
+    pub fn with_size(
+        players: usize,
+        width: usize,
+        height: usize,
+        win_length: usize,
+    ) -> Result<Self, String> {
+        if !(2..=SYMBOLS.len()).contains(&players) {
+            return Err(format!(
+                "a game needs between 2 and {} players, got {}",
+                SYMBOLS.len(),
+                players
+            ));
+        }
+        check_size(width, height, win_length)?;
+        Ok(MultiplayerGame {
+            players: vec![None; players],
+            symbols: SYMBOLS[..players].to_vec(),
+            current_player_turn: None,
+            to_move: 1,
+            eliminated: vec![false; players],
+            width,
+            height,
+            win_length,
+            grid: Marks::new(width, height),
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        })
+    }
+
+    /// the seat number of `player_id`, if they are playing
+    fn seat_of(&self, player_id: usize) -> Option<usize> {
+        self.players
+            .iter()
+            .position(|&player| player == Some(player_id))
+            .map(|i| i + 1)
+    }
+
+    /// whether the seat numbered `seat` is taken and still in the game
+    fn is_playing(&self, seat: usize) -> bool {
+        self.players[seat - 1].is_some() && !self.eliminated[seat - 1]
+    }
+
+    /// give the turn to the first seat from `to_move` on that is still
+    /// playing. nobody moves before every seat has been filled once, or
+    /// while fewer than two players are left at the table
+    fn start_turn(&mut self) {
+        self.current_player_turn = None;
+        if self.outcome.is_over() {
+            return;
+        }
+        if self.moves.is_empty() && self.players.iter().any(Option::is_none) {
+            return;
+        }
+        let seats = self.players.len();
+        if (1..=seats).filter(|&seat| self.is_playing(seat)).count() < 2 {
+            return;
+        }
+        let next = (0..seats)
+            .map(|step| (self.to_move - 1 + step) % seats + 1)
+            .find(|&seat| self.is_playing(seat));
+        if let Some(seat) = next {
+            self.to_move = seat;
+            self.current_player_turn = self.players[seat - 1];
+        }
+    }
+
+    /// the seat of `player_id` and where they play, if they may
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(usize, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        match self.current_player_turn {
+            Some(current) if current == player_id => {}
+            Some(_) => return Err(MoveError::NotYourTurn),
+            None => return Err(MoveError::GameNotStarted),
+        }
+        let (x, y) = match turn.position.as_slice() {
+            [x, y] => (*x, *y),
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= self.width || y >= self.height {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.grid.get(x, y) != 0 {
+            return Err(MoveError::Occupied);
+        }
+        Ok((self.to_move, x, y))
+    }
+
+    /// a line through (x, y) that `seat` has filled
+    fn line_through(&self, seat: usize, x: usize, y: usize) -> Option<Bitboard> {
+        let marks = self.grid.of(seat);
+        win_masks(self.width, self.height, self.win_length)
+            .iter()
+            .find(|line| line.contains(x, y) && marks.contains_all(**line))
+            .cloned()
+    }
+}
+
+impl Game for MultiplayerGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.players.clone()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        if self.seat_of(id).is_some() {
+            return;
+        }
+        // knocked out seats stay out, whoever sits down
+        let free =
+            (0..self.players.len()).find(|&i| self.players[i].is_none() && !self.eliminated[i]);
+        if let Some(i) = free {
+            self.players[i] = Some(id);
+            self.start_turn();
+        }
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        if let Some(seat) = self.seat_of(id) {
+            self.players[seat - 1] = None;
+            self.start_turn();
+        }
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, x, y) = self.check_move(player_id, turn)?;
+        self.grid.set(x, y, seat);
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        if let Some(line) = self.line_through(seat, x, y) {
+            self.outcome = GameOutcome::Won {
+                player: player_id,
+                cells: line
+                    .squares()
+                    .into_iter()
+                    .map(|(x, y)| vec![x, y])
+                    .collect(),
+            };
+        } else if self.grid.empty().is_empty() {
+            self.outcome = GameOutcome::Draw;
+        }
+        self.winner = self.outcome.winner();
+        self.to_move = seat % self.players.len() + 1;
+        self.start_turn();
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        self.grid
+            .empty()
+            .squares()
+            .into_iter()
+            .map(|(x, y)| GameTurnMessage {
+                position: vec![x, y],
+            })
+            .collect()
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    /// knock `player_id` out, which ends the game once only one seat is left
+    fn forfeit(&mut self, player_id: usize) {
+        if self.outcome.is_over() {
+            return;
+        }
+        let seat = match self.seat_of(player_id) {
+            Some(seat) => seat,
+            None => return,
+        };
+        self.eliminated[seat - 1] = true;
+        let left: Vec<usize> = (0..self.players.len())
+            .filter(|&i| !self.eliminated[i])
+            .collect();
+        if let [last] = left.as_slice() {
+            self.outcome = GameOutcome::forfeited_to(self.players[*last]);
+            self.winner = self.outcome.winner();
+        }
+        self.start_turn();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated(players: usize) -> MultiplayerGame {
+        let mut g = MultiplayerGame::with_size(players, 6, 6, 4).unwrap();
+        for id in 1..=players {
+            g.add_player(id);
+        }
+        g
+    }
+
+    fn play(
+        g: &mut MultiplayerGame,
+        player_id: usize,
+        position: Vec<usize>,
+    ) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn seats_move_in_order_with_their_own_symbols() {
+        let mut g = MultiplayerGame::with_size(3, 6, 6, 4).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::GameNotStarted));
+        g.add_player(3);
+        assert_eq!(g.current_player(), Some(1));
+        assert_eq!(play(&mut g, 3, vec![0, 0]), Err(MoveError::NotYourTurn));
+        for (x, player) in (1..=3).enumerate() {
+            play(&mut g, player, vec![x, 0]).unwrap();
+        }
+        assert_eq!(g.grid.rows()[0][..4], [1, 2, 3, 0]);
+        assert_eq!(g.current_player(), Some(1));
+        let json = g.state();
+        assert_eq!(json["symbols"], serde_json::json!(["x", "o", "+"]));
+        assert_eq!(json["grid"][0], serde_json::json!([1, 2, 3, 0, 0, 0]));
+        let back: MultiplayerGame = serde_json::from_value(json).unwrap();
+        assert_eq!(back.grid, g.grid);
+        assert!(MultiplayerGame::with_size(7, 6, 6, 4).is_err());
+    }
+
+    #[test]
+    fn first_to_fill_a_line_wins() {
+        let mut g = seated(3);
+        for y in 0..3 {
+            for player in 1..=3 {
+                play(&mut g, player, vec![player - 1, y]).unwrap();
+            }
+        }
+        play(&mut g, 1, vec![0, 3]).unwrap();
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: (0..4).map(|y| vec![0, y]).collect(),
+            }
+        );
+        assert_eq!(g.current_player(), None);
+    }
+
+    #[test]
+    fn forfeiting_knocks_a_player_out_until_one_is_left() {
+        let mut g = seated(3);
+        g.forfeit(2);
+        assert_eq!(g.outcome, GameOutcome::InProgress);
+        play(&mut g, 1, vec![0, 0]).unwrap();
+        assert_eq!(g.current_player(), Some(3));
+        // the knocked out seat can't be taken over either
+        g.remove_player(2);
+        g.add_player(4);
+        assert_eq!(g.seats(), vec![Some(1), None, Some(3)]);
+        g.forfeit(3);
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(g.current_player(), None);
+    }
+
+    #[test]
+    fn players_who_leave_are_skipped_until_someone_sits_down() {
+        let mut g = seated(3);
+        play(&mut g, 1, vec![0, 0]).unwrap();
+        g.remove_player(2);
+        assert_eq!(g.current_player(), Some(3));
+        play(&mut g, 3, vec![1, 0]).unwrap();
+        assert_eq!(g.current_player(), Some(1));
+        g.add_player(4);
+        play(&mut g, 1, vec![2, 0]).unwrap();
+        assert_eq!(g.current_player(), Some(4));
+        // with only one player at the table nobody moves
+        g.remove_player(4);
+        g.remove_player(3);
+        assert_eq!(g.current_player(), None);
+    }
+}
@@ -0,0 +1,204 @@
+//! Notakto, misère tic-tac-toe on several 3x3 boards at once. Both players
+//! place x on any board still in play, a board dies as soon as it has three
+//! in a row, and whoever kills the last one loses.
+//!
+//! Moves are `[board, x, y]`, boards counted from 0.
+
+use bitboard::{win_masks, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+const SIDE: usize = 3;
+
+/// the most boards a room can ask for
+pub const MAX_BOARDS: usize = 9;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NotaktoGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// every board as rows of cells, where every mark is an x whoever made
+    /// it and so shows up as 1
+    pub boards: Vec<Board>,
+    /// which boards have three in a row and can't be played on any more
+    pub dead: Vec<bool>,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl NotaktoGame {
+    /// a game on `boards` empty boards
+    pub fn with_boards(boards: usize) -> Result<Self, String> {
+        if boards == 0 || boards > MAX_BOARDS {
+            return Err(format!(
+                "notakto needs between 1 and {} boards, got {}",
+                MAX_BOARDS, boards
+            ));
+        }
+        Ok(NotaktoGame {
+            seating: Seating::default(),
+            boards: vec![Board::new(SIDE, SIDE); boards],
+            dead: vec![false; boards],
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        })
+    }
+
+    /// the seat of `player_id` and where they play, if they may
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, usize, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let (board, x, y) = match turn.position.as_slice() {
+            [board, x, y] => (*board, *x, *y),
+            _ => return Err(MoveError::Malformed),
+        };
+        if board >= self.boards.len() || x >= SIDE || y >= SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.dead[board] {
+            return Err(MoveError::DeadBoard);
+        }
+        if self.boards[board].get(x, y) != Cell::Empty {
+            return Err(MoveError::Occupied);
+        }
+        Ok((seat, board, x, y))
+    }
+}
+
+impl Game for NotaktoGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, board, x, y) = self.check_move(player_id, turn)?;
+        self.boards[board].set(x, y, Cell::Taken(Seat::One));
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        let masks = win_masks(SIDE, SIDE, SIDE);
+        if let Some((_, line)) = self.boards[board].completed_line(&masks) {
+            self.dead[board] = true;
+            // killing the last board hands the game to the other side
+            if self.dead.iter().all(|&dead| dead) {
+                self.outcome = match self.seating.opponent(player_id) {
+                    Some(player) => GameOutcome::Won {
+                        player,
+                        cells: line
+                            .squares()
+                            .into_iter()
+                            .map(|(x, y)| vec![board, x, y])
+                            .collect(),
+                    },
+                    None => GameOutcome::Draw,
+                };
+                self.winner = self.outcome.winner();
+                self.seating.current_player_turn = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for (board, grid) in self.boards.iter().enumerate() {
+            if self.dead[board] {
+                continue;
+            }
+            for (x, y) in grid.empty().squares() {
+                moves.push(GameTurnMessage {
+                    position: vec![board, x, y],
+                });
+            }
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seated(boards: usize) -> NotaktoGame {
+        let mut g = NotaktoGame::with_boards(boards).unwrap();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut NotaktoGame, player_id: usize, position: Vec<usize>) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn both_players_place_x() {
+        let mut g = seated(2);
+        assert_eq!(g.legal_moves().len(), 18);
+        play(&mut g, 1, vec![0, 0, 0]).unwrap();
+        play(&mut g, 2, vec![1, 1, 1]).unwrap();
+        assert_eq!(g.boards[0].get(0, 0), Cell::Taken(Seat::One));
+        assert_eq!(g.boards[1].get(1, 1), Cell::Taken(Seat::One));
+        assert_eq!(play(&mut g, 1, vec![2, 0, 0]), Err(MoveError::OutOfBounds));
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::Malformed));
+        assert!(NotaktoGame::with_boards(0).is_err());
+    }
+
+    #[test]
+    fn killing_the_last_board_loses() {
+        let mut g = seated(2);
+        // player 1 kills board 0, which is fine while board 1 lives
+        for &(player, x) in &[(1, 0), (2, 1), (1, 2)] {
+            play(&mut g, player, vec![0, x, 0]).unwrap();
+        }
+        assert_eq!(g.dead, vec![true, false]);
+        assert_eq!(g.outcome, GameOutcome::InProgress);
+        assert_eq!(play(&mut g, 2, vec![0, 1, 1]), Err(MoveError::DeadBoard));
+        assert_eq!(g.legal_moves().len(), 9);
+        for &(player, y) in &[(2, 0), (1, 1), (2, 2)] {
+            play(&mut g, player, vec![1, 0, y]).unwrap();
+        }
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![1, 0, 0], vec![1, 0, 1], vec![1, 0, 2]],
+            }
+        );
+    }
+}
@@ -0,0 +1,238 @@
+//! Numerical tic-tac-toe on the classic 3x3 board. Instead of marks the
+//! first player places the odd numbers 1 to 9 and the second the even ones,
+//! each number once, and whoever completes a line of three adding up to 15
+//! wins, whichever numbers are in it.
+//!
+//! Moves are `[x, y, number]`.
+
+use bitboard::win_masks;
+use game::{Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+const SIDE: usize = 3;
+
+/// what a full line has to add up to
+const TARGET: usize = 15;
+
+/// the highest number anyone can place
+const HIGHEST: usize = SIDE * SIDE;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NumericalGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// rows of numbers from the top, 0 where nothing has been placed
+    pub grid: Vec<Vec<usize>>,
+    /// the numbers each seat still has to place, seat 1's odd ones first
+    pub numbers_left: Vec<Vec<usize>>,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl Default for NumericalGame {
+    fn default() -> Self {
+        NumericalGame {
+            seating: Seating::default(),
+            grid: vec![vec![0; SIDE]; SIDE],
+            numbers_left: vec![
+                (1..=HIGHEST).filter(|n| n % 2 == 1).collect(),
+                (1..=HIGHEST).filter(|n| n % 2 == 0).collect(),
+            ],
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        }
+    }
+}
+
+impl NumericalGame {
+    pub fn new() -> Self {
+        NumericalGame::default()
+    }
+
+    fn numbers_of(&self, seat: Seat) -> &[usize] {
+        &self.numbers_left[seat.number() as usize - 1]
+    }
+
+    /// the seat of `player_id` and where and what they place, if they may
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, usize, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let (x, y, number) = match turn.position.as_slice() {
+            [x, y, number] => (*x, *y, *number),
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= SIDE || y >= SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.grid[y][x] != 0 {
+            return Err(MoveError::Occupied);
+        }
+        if !self.numbers_of(seat).contains(&number) {
+            return Err(MoveError::NumberNotYours);
+        }
+        Ok((seat, x, y, number))
+    }
+
+    /// the first full line adding up to 15, as its squares
+    fn winning_line(&self) -> Option<Vec<(usize, usize)>> {
+        win_masks(SIDE, SIDE, SIDE)
+            .iter()
+            .map(|line| line.squares())
+            .find(|squares| {
+                squares.iter().all(|&(x, y)| self.grid[y][x] != 0)
+                    && squares.iter().map(|&(x, y)| self.grid[y][x]).sum::<usize>() == TARGET
+            })
+    }
+
+    /// where the game stands right after `seat` moved
+    fn get_outcome(&self, seat: Seat) -> GameOutcome {
+        let winner = self
+            .winning_line()
+            .and_then(|line| Some((self.seating.player_in(seat)?, line)));
+        if let Some((player, line)) = winner {
+            return GameOutcome::Won {
+                player,
+                cells: line.into_iter().map(|(x, y)| vec![x, y]).collect(),
+            };
+        }
+        if self.grid.iter().all(|row| row.iter().all(|&n| n != 0)) {
+            GameOutcome::Draw
+        } else {
+            GameOutcome::InProgress
+        }
+    }
+}
+
+impl Game for NumericalGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, x, y, number) = self.check_move(player_id, turn)?;
+        self.grid[y][x] = number;
+        self.numbers_left[seat.number() as usize - 1].retain(|&n| n != number);
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        self.outcome = self.get_outcome(seat);
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let numbers = self.numbers_of(self.seating.to_move);
+        let mut moves = Vec::new();
+        for y in 0..SIDE {
+            for x in 0..SIDE {
+                if self.grid[y][x] != 0 {
+                    continue;
+                }
+                for &number in numbers {
+                    moves.push(GameTurnMessage {
+                        position: vec![x, y, number],
+                    });
+                }
+            }
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated() -> NumericalGame {
+        let mut g = NumericalGame::new();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(
+        g: &mut NumericalGame,
+        player_id: usize,
+        position: Vec<usize>,
+    ) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn each_side_has_its_own_numbers() {
+        let mut g = seated();
+        assert_eq!(g.legal_moves().len(), 9 * 5);
+        assert_eq!(
+            play(&mut g, 1, vec![0, 0, 2]),
+            Err(MoveError::NumberNotYours)
+        );
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::Malformed));
+        play(&mut g, 1, vec![0, 0, 9]).unwrap();
+        assert_eq!(g.legal_moves().len(), 8 * 4);
+        play(&mut g, 2, vec![1, 1, 2]).unwrap();
+        // nobody gets to use a number twice
+        assert_eq!(
+            play(&mut g, 1, vec![2, 2, 9]),
+            Err(MoveError::NumberNotYours)
+        );
+        assert_eq!(g.numbers_left[0], vec![1, 3, 5, 7]);
+    }
+
+    #[test]
+    fn a_line_adding_up_to_15_wins_for_whoever_finished_it() {
+        let mut g = seated();
+        play(&mut g, 1, vec![0, 0, 7]).unwrap();
+        play(&mut g, 2, vec![1, 0, 6]).unwrap();
+        // the diagonal and top row are still short of a third number
+        play(&mut g, 1, vec![1, 1, 1]).unwrap();
+        assert_eq!(g.outcome, GameOutcome::InProgress);
+        play(&mut g, 2, vec![2, 0, 2]).unwrap();
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 2,
+                cells: vec![vec![0, 0], vec![1, 0], vec![2, 0]],
+            }
+        );
+        assert_eq!(g.state()["grid"][0], serde_json::json!([7, 6, 2]));
+    }
+}
@@ -0,0 +1,223 @@
+//! Order and Chaos on a 6x6 board. Both players may place either mark, so a
+//! move is `[x, y, mark]` with the mark as a seat number, 1 for x and 2 for
+//! o, like wild tic-tac-toe. Order, in seat 1, wins with five of one mark in
+//! a row. Chaos, in seat 2, wins by filling the board without that happening.
+
+use bitboard::{win_masks, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+const SIDE: usize = 6;
+
+/// how many of one mark in a row Order needs
+const ORDER_LENGTH: usize = 5;
+
+/// the seat that plays Order, Chaos has the other one
+const ORDER: Seat = Seat::One;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderChaosGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// the marks, which say nothing about who placed them
+    pub grid: Board,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl Default for OrderChaosGame {
+    fn default() -> Self {
+        OrderChaosGame {
+            seating: Seating::default(),
+            grid: Board::new(SIDE, SIDE),
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        }
+    }
+}
+
+impl OrderChaosGame {
+    pub fn new() -> Self {
+        OrderChaosGame::default()
+    }
+
+    /// the seat of `player_id`, the mark and the square, if they may play
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, Seat, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let (x, y, mark) = match turn.position.as_slice() {
+            [x, y, mark] => match Seat::from_number(*mark as u64) {
+                Some(mark) => (*x, *y, mark),
+                None => return Err(MoveError::Malformed),
+            },
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= SIDE || y >= SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.grid.get(x, y) != Cell::Empty {
+            return Err(MoveError::Occupied);
+        }
+        Ok((seat, mark, x, y))
+    }
+
+    /// Order as soon as five line up, Chaos once the board fills without
+    fn get_outcome(&self) -> GameOutcome {
+        let masks = win_masks(SIDE, SIDE, ORDER_LENGTH);
+        if let Some((_, line)) = self.grid.completed_line(&masks) {
+            if let Some(player) = self.seating.player_in(ORDER) {
+                return GameOutcome::Won {
+                    player,
+                    cells: line
+                        .squares()
+                        .into_iter()
+                        .map(|(x, y)| vec![x, y])
+                        .collect(),
+                };
+            }
+        }
+        if !self.grid.is_full() {
+            return GameOutcome::InProgress;
+        }
+        match self.seating.player_in(ORDER.next()) {
+            Some(player) => GameOutcome::Won {
+                player,
+                cells: Vec::new(),
+            },
+            None => GameOutcome::InProgress,
+        }
+    }
+}
+
+impl Game for OrderChaosGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, mark, x, y) = self.check_move(player_id, turn)?;
+        self.grid.set(x, y, Cell::Taken(mark));
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        self.outcome = self.get_outcome();
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for (x, y) in self.grid.empty().squares() {
+            for mark in 1..=2 {
+                moves.push(GameTurnMessage {
+                    position: vec![x, y, mark],
+                });
+            }
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seated() -> OrderChaosGame {
+        let mut g = OrderChaosGame::new();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(
+        g: &mut OrderChaosGame,
+        player_id: usize,
+        position: Vec<usize>,
+    ) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn moves_name_their_mark() {
+        let mut g = seated();
+        assert_eq!(g.legal_moves().len(), 72);
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::Malformed));
+        assert_eq!(play(&mut g, 1, vec![0, 0, 3]), Err(MoveError::Malformed));
+        play(&mut g, 1, vec![0, 0, 2]).unwrap();
+        assert_eq!(g.grid.get(0, 0), Cell::Taken(Seat::Two));
+    }
+
+    #[test]
+    fn five_in_a_row_wins_for_order_whoever_made_it() {
+        let mut g = seated();
+        // chaos is made to play the o that completes the column
+        for y in 0..4 {
+            play(&mut g, 1, vec![0, y, 2]).unwrap();
+            play(&mut g, 2, vec![5, y, 1]).unwrap();
+        }
+        play(&mut g, 1, vec![3, 3, 1]).unwrap();
+        play(&mut g, 2, vec![0, 4, 2]).unwrap();
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: (0..5).map(|y| vec![0, y]).collect(),
+            }
+        );
+    }
+
+    #[test]
+    fn a_full_board_without_five_is_a_win_for_chaos() {
+        let mut g = seated();
+        // pairs of x and o along the rows, flipping every row, never line
+        // up five of anything
+        for y in 0..SIDE {
+            for x in 0..SIDE {
+                let mark = Seat::from_number(((x / 2 + y) % 2 + 1) as u64).unwrap();
+                g.grid.set(x, y, Cell::Taken(mark));
+            }
+        }
+        g.grid.set(5, 5, Cell::Empty);
+        play(&mut g, 1, vec![5, 5, 2]).unwrap();
+        assert_eq!(g.winner, Some(2));
+    }
+}
@@ -0,0 +1,469 @@
+//! Quantum tic-tac-toe on the classic 3x3 board. Every move puts a
+//! "spooky" mark, numbered by when it was played, in two squares at once,
+//! `[x1, y1, x2, y2]`. Marks sharing squares are entangled, and once a
+//! move closes a cycle of them the other player picks which of its two
+//! squares the newest mark collapses into, `[x, y]`. That settles every
+//! mark in the cycle and anything hanging off it into plain, classical
+//! marks, and only classical marks make lines.
+//!
+//! When one collapse gives both players a line, the line finished with the
+//! earlier numbered mark wins: its player scores 1 and the other ½. When
+//! only one square is left it can only take a classical mark, `[x, y]`.
+
+use bitboard::win_masks;
+use game::{Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const SIDE: usize = 3;
+
+/// a mark and the move it was played on, counting from 1
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Mark {
+    pub seat: Seat,
+    pub number: usize,
+}
+
+/// one square of the board, sent to clients as is
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Square {
+    /// the mark the square settled on, if it has
+    pub classical: Option<Mark>,
+    /// the marks that may still end up here, oldest first
+    pub spooky: Vec<Mark>,
+}
+
+/// a cycle waiting on the other player to say how it collapses
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Collapse {
+    /// the mark that closed the cycle
+    pub mark: Mark,
+    /// the two squares it may collapse into
+    pub squares: Vec<(usize, usize)>,
+}
+
+/// a line of classical marks, with the number of the mark that finished it
+type FinishedLine = (usize, Vec<(usize, usize)>);
+
+/// what a move that passed `check_move` does
+enum Play {
+    Spooky((usize, usize), (usize, usize)),
+    Collapse(usize, usize),
+    /// the last free square takes a plain mark
+    Classical(usize, usize),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuantumGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// rows of squares from the top
+    pub squares: Vec<Vec<Square>>,
+    /// the number the next mark gets
+    pub next_mark: usize,
+    /// the cycle the player to move has to collapse before anything else
+    pub collapse: Option<Collapse>,
+    /// points per seat once the game is over, seat 1 first
+    pub scores: Vec<f64>,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl Default for QuantumGame {
+    fn default() -> Self {
+        QuantumGame {
+            seating: Seating::default(),
+            squares: vec![vec![Square::default(); SIDE]; SIDE],
+            next_mark: 1,
+            collapse: None,
+            scores: vec![0.0, 0.0],
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        }
+    }
+}
+
+impl QuantumGame {
+    pub fn new() -> Self {
+        QuantumGame::default()
+    }
+
+    fn square(&self, (x, y): (usize, usize)) -> &Square {
+        &self.squares[y][x]
+    }
+
+    /// every square without a classical mark yet
+    fn open_squares(&self) -> Vec<(usize, usize)> {
+        let mut open = Vec::new();
+        for y in 0..SIDE {
+            for x in 0..SIDE {
+                if self.squares[y][x].classical.is_none() {
+                    open.push((x, y));
+                }
+            }
+        }
+        open
+    }
+
+    /// the squares holding a spooky `mark`
+    fn squares_of(&self, mark: Mark) -> Vec<(usize, usize)> {
+        self.open_squares()
+            .into_iter()
+            .filter(|&square| self.square(square).spooky.contains(&mark))
+            .collect()
+    }
+
+    /// whether spooky marks already join `from` to `to`, in which case one
+    /// more between them closes a cycle
+    fn entangled(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let mut seen = vec![from];
+        let mut queue = VecDeque::new();
+        queue.push_back(from);
+        while let Some(square) = queue.pop_front() {
+            if square == to {
+                return true;
+            }
+            for &mark in &self.square(square).spooky {
+                for other in self.squares_of(mark) {
+                    if !seen.contains(&other) {
+                        seen.push(other);
+                        queue.push_back(other);
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    /// settle `mark` in `square`, which pushes every other spooky mark there
+    /// into its other square, and so on down the chain
+    fn collapse_into(&mut self, mark: Mark, square: (usize, usize)) {
+        let mut queue = VecDeque::new();
+        queue.push_back((mark, square));
+        while let Some((mark, (x, y))) = queue.pop_front() {
+            if self.squares[y][x].classical.is_some() {
+                continue;
+            }
+            let pushed_out = std::mem::take(&mut self.squares[y][x].spooky);
+            self.squares[y][x].classical = Some(mark);
+            for row in self.squares.iter_mut() {
+                for other in row.iter_mut() {
+                    other.spooky.retain(|&spooky| spooky != mark);
+                }
+            }
+            for other in pushed_out.into_iter().filter(|&other| other != mark) {
+                if let Some(&square) = self.squares_of(other).first() {
+                    queue.push_back((other, square));
+                }
+            }
+        }
+    }
+
+    /// the seat of `player_id` and what their move does, if they may play it
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, Play), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        if turn.position.iter().any(|&coordinate| coordinate >= SIDE) {
+            return Err(MoveError::OutOfBounds);
+        }
+        let open = self.open_squares();
+        match (turn.position.as_slice(), &self.collapse) {
+            ([x, y], Some(collapse)) if collapse.squares.contains(&(*x, *y)) => {
+                Ok((seat, Play::Collapse(*x, *y)))
+            }
+            ([_, _], Some(_)) => Err(MoveError::BadCollapse),
+            (_, Some(_)) => Err(MoveError::CollapsePending),
+            ([x, y], None) if open == [(*x, *y)] => Ok((seat, Play::Classical(*x, *y))),
+            ([x, y], None) if open.len() == 1 || !open.contains(&(*x, *y)) => {
+                Err(MoveError::Occupied)
+            }
+            ([x1, y1, x2, y2], None) if open.len() > 1 => {
+                let (first, second) = ((*x1, *y1), (*x2, *y2));
+                if first == second {
+                    return Err(MoveError::Malformed);
+                }
+                if !open.contains(&first) || !open.contains(&second) {
+                    return Err(MoveError::Occupied);
+                }
+                Ok((seat, Play::Spooky(first, second)))
+            }
+            _ => Err(MoveError::Malformed),
+        }
+    }
+
+    /// end and score the game if the classical marks make a line or fill
+    /// the board
+    fn settle(&mut self) {
+        // the earliest line each seat has, by the last mark that finished it
+        let mut best: Vec<Option<FinishedLine>> = vec![None, None];
+        for line in win_masks(SIDE, SIDE, SIDE).iter() {
+            let squares = line.squares();
+            let marks: Vec<Mark> = squares
+                .iter()
+                .filter_map(|&square| self.square(square).classical)
+                .collect();
+            if marks.len() < SIDE || marks.iter().any(|mark| mark.seat != marks[0].seat) {
+                continue;
+            }
+            let finished = marks.iter().map(|mark| mark.number).max().unwrap_or(0);
+            let seat = marks[0].seat.number() as usize - 1;
+            let earlier = match &best[seat] {
+                Some((at, _)) => finished < *at,
+                None => true,
+            };
+            if earlier {
+                best[seat] = Some((finished, squares));
+            }
+        }
+        let first = match (&best[0], &best[1]) {
+            (Some((one, _)), Some((two, _))) if two < one => Some(Seat::Two),
+            (Some(_), _) => Some(Seat::One),
+            (None, Some(_)) => Some(Seat::Two),
+            (None, None) => None,
+        };
+        let first = first.and_then(|seat| Some((seat, self.seating.player_in(seat)?)));
+        self.outcome = match first {
+            Some((seat, player)) => {
+                let index = seat.number() as usize - 1;
+                self.scores[index] = 1.0;
+                if best[1 - index].is_some() {
+                    self.scores[1 - index] = 0.5;
+                }
+                let (_, line) = best[index].take().unwrap_or_default();
+                GameOutcome::Won {
+                    player,
+                    cells: line.into_iter().map(|(x, y)| vec![x, y]).collect(),
+                }
+            }
+            None if self.open_squares().is_empty() => GameOutcome::Draw,
+            None => GameOutcome::InProgress,
+        };
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+    }
+}
+
+impl Game for QuantumGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, play) = self.check_move(player_id, turn)?;
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        match play {
+            Play::Spooky(first, second) => {
+                let mark = Mark {
+                    seat,
+                    number: self.next_mark,
+                };
+                self.next_mark += 1;
+                let closes_cycle = self.entangled(first, second);
+                self.squares[first.1][first.0].spooky.push(mark);
+                self.squares[second.1][second.0].spooky.push(mark);
+                if closes_cycle {
+                    self.collapse = Some(Collapse {
+                        mark,
+                        squares: vec![first, second],
+                    });
+                }
+                self.seating.pass_turn(seat);
+            }
+            // whoever collapses the cycle moves straight after
+            Play::Collapse(x, y) => {
+                if let Some(collapse) = self.collapse.take() {
+                    self.collapse_into(collapse.mark, (x, y));
+                }
+                self.settle();
+            }
+            Play::Classical(x, y) => {
+                let mark = Mark {
+                    seat,
+                    number: self.next_mark,
+                };
+                self.next_mark += 1;
+                self.squares[y][x].classical = Some(mark);
+                self.seating.pass_turn(seat);
+                self.settle();
+            }
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        if let Some(collapse) = &self.collapse {
+            return collapse
+                .squares
+                .iter()
+                .map(|&(x, y)| GameTurnMessage {
+                    position: vec![x, y],
+                })
+                .collect();
+        }
+        let open = self.open_squares();
+        if let [(x, y)] = open.as_slice() {
+            return vec![GameTurnMessage {
+                position: vec![*x, *y],
+            }];
+        }
+        let mut moves = Vec::new();
+        for (i, &(x1, y1)) in open.iter().enumerate() {
+            for &(x2, y2) in &open[i + 1..] {
+                moves.push(GameTurnMessage {
+                    position: vec![x1, y1, x2, y2],
+                });
+            }
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated() -> QuantumGame {
+        let mut g = QuantumGame::new();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut QuantumGame, player_id: usize, position: Vec<usize>) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn marks_go_in_two_squares() {
+        let mut g = seated();
+        assert_eq!(g.legal_moves().len(), 36);
+        assert_eq!(play(&mut g, 1, vec![0, 0, 0, 0]), Err(MoveError::Malformed));
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::Malformed));
+        play(&mut g, 1, vec![0, 0, 1, 1]).unwrap();
+        let mark = Mark {
+            seat: Seat::One,
+            number: 1,
+        };
+        assert_eq!(g.squares[0][0].spooky, vec![mark]);
+        assert_eq!(g.squares[1][1].spooky, vec![mark]);
+        assert_eq!(g.current_player(), Some(2));
+        assert_eq!(g.collapse, None);
+    }
+
+    #[test]
+    fn a_cycle_is_collapsed_by_the_other_player() {
+        let mut g = seated();
+        play(&mut g, 1, vec![0, 0, 1, 0]).unwrap();
+        play(&mut g, 2, vec![1, 0, 2, 0]).unwrap();
+        // x's second mark closes the cycle a1 - b1 - c1 - a1
+        play(&mut g, 1, vec![2, 0, 0, 0]).unwrap();
+        assert_eq!(g.legal_moves().len(), 2);
+        assert_eq!(
+            play(&mut g, 2, vec![1, 1, 2, 2]),
+            Err(MoveError::CollapsePending)
+        );
+        assert_eq!(play(&mut g, 2, vec![1, 1]), Err(MoveError::BadCollapse));
+        // o puts x3 in a1, which pushes x1 to b1 and o2 to c1
+        play(&mut g, 2, vec![0, 0]).unwrap();
+        let classical: Vec<Option<usize>> = g.squares[0]
+            .iter()
+            .map(|square| square.classical.map(|mark| mark.number))
+            .collect();
+        assert_eq!(classical, vec![Some(3), Some(1), Some(2)]);
+        assert!(g.squares[0].iter().all(|square| square.spooky.is_empty()));
+        assert_eq!(g.collapse, None);
+        // and it's still o's turn, now for a move of their own
+        assert_eq!(g.current_player(), Some(2));
+        assert_eq!(g.legal_moves().len(), 15);
+    }
+
+    #[test]
+    fn the_earlier_of_two_lines_wins() {
+        let mut g = seated();
+        let cross = |number| {
+            Some(Mark {
+                seat: Seat::One,
+                number,
+            })
+        };
+        let nought = |number| {
+            Some(Mark {
+                seat: Seat::Two,
+                number,
+            })
+        };
+        // x's top row was done by mark 5, o's bottom row only by mark 6
+        let rows = [
+            [cross(1), cross(3), cross(5)],
+            [None, None, None],
+            [nought(2), nought(4), nought(6)],
+        ];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &mark) in row.iter().enumerate() {
+                g.squares[y][x].classical = mark;
+            }
+        }
+        g.settle();
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(g.scores, vec![1.0, 0.5]);
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![0, 0], vec![1, 0], vec![2, 0]],
+            }
+        );
+    }
+
+    #[test]
+    fn state_lists_spooky_marks_per_square() {
+        let mut g = seated();
+        play(&mut g, 1, vec![2, 1, 0, 2]).unwrap();
+        let json = g.state();
+        assert_eq!(
+            json["squares"][1][2],
+            serde_json::json!({"classical": null, "spooky": [{"seat": 1, "number": 1}]})
+        );
+        assert_eq!(json["collapse"], serde_json::Value::Null);
+        assert_eq!(json["next_mark"], 2);
+    }
+}
@@ -0,0 +1,334 @@
+//! Qubic, tic-tac-toe on a 4x4x4 cube. Moves are `[x, y, z]` and four in a
+//! row along any of the cube's 76 lines wins, including the ones that run
+//! through the layers and the four corner to corner diagonals.
+
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// squares along every edge of the cube
+const SIDE: usize = 4;
+
+thread_local! {
+    static LINES: Vec<u64> = build_lines();
+}
+
+/// the bit for square (x, y, z), one bit per square fits the cube in a u64
+fn bit(x: usize, y: usize, z: usize) -> u64 {
+    1 << (z * SIDE * SIDE + y * SIDE + x)
+}
+
+/// every run of four squares through the cube
+fn build_lines() -> Vec<u64> {
+    let mut lines = Vec::new();
+    let steps = [-1isize, 0, 1];
+    for &dz in &steps {
+        for &dy in &steps {
+            for &dx in &steps {
+                // only count each direction one way round
+                if (dz, dy, dx) <= (0, 0, 0) {
+                    continue;
+                }
+                for z in 0..SIDE as isize {
+                    for y in 0..SIDE as isize {
+                        for x in 0..SIDE as isize {
+                            let end = |start: isize, step: isize| start + step * 3;
+                            let inside = |v: isize| v >= 0 && v < SIDE as isize;
+                            if !inside(end(x, dx)) || !inside(end(y, dy)) || !inside(end(z, dz)) {
+                                continue;
+                            }
+                            let line = (0..SIDE as isize).fold(0, |line, i| {
+                                line | bit(
+                                    (x + dx * i) as usize,
+                                    (y + dy * i) as usize,
+                                    (z + dz * i) as usize,
+                                )
+                            });
+                            lines.push(line);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// the marks in the cube, one u64 per seat. it goes over the wire as layers
+/// from `z = 0` up, each as rows of cells like a flat board
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Cube {
+    marks: [u64; 2],
+}
+
+impl Cube {
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Cell {
+        if self.marks[0] & bit(x, y, z) != 0 {
+            Cell::Taken(Seat::One)
+        } else if self.marks[1] & bit(x, y, z) != 0 {
+            Cell::Taken(Seat::Two)
+        } else {
+            Cell::Empty
+        }
+    }
+
+    /// put `cell` at (x, y, z). qubic has no blocked squares, so
+    /// `Cell::Blocked` just empties it
+    pub fn set(&mut self, x: usize, y: usize, z: usize, cell: Cell) {
+        self.marks[0] &= !bit(x, y, z);
+        self.marks[1] &= !bit(x, y, z);
+        if let Cell::Taken(seat) = cell {
+            self.marks[seat.number() as usize - 1] |= bit(x, y, z);
+        }
+    }
+
+    /// the (x, y, z) of every square nobody has marked yet
+    pub fn empty_squares(&self) -> Vec<(usize, usize, usize)> {
+        let taken = self.marks[0] | self.marks[1];
+        (0..SIDE * SIDE * SIDE)
+            .filter(|i| taken & (1 << i) == 0)
+            .map(|i| (i % SIDE, i / SIDE % SIDE, i / (SIDE * SIDE)))
+            .collect()
+    }
+
+    /// the first line one seat has filled, with whose it is and its squares
+    pub fn completed_line(&self) -> Option<(Seat, Vec<Vec<usize>>)> {
+        LINES.with(|lines| {
+            for &line in lines {
+                for &seat in &[Seat::One, Seat::Two] {
+                    if self.marks[seat.number() as usize - 1] & line == line {
+                        let squares = (0..SIDE * SIDE * SIDE)
+                            .filter(|i| line & (1 << i) != 0)
+                            .map(|i| vec![i % SIDE, i / SIDE % SIDE, i / (SIDE * SIDE)])
+                            .collect();
+                        return Some((seat, squares));
+                    }
+                }
+            }
+            None
+        })
+    }
+
+    fn layers(&self) -> Vec<Vec<Vec<Cell>>> {
+        (0..SIDE)
+            .map(|z| {
+                (0..SIDE)
+                    .map(|y| (0..SIDE).map(|x| self.get(x, y, z)).collect())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Serialize for Cube {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.layers().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cube {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let layers = Vec::<Vec<Vec<Cell>>>::deserialize(deserializer)?;
+        let fits = layers.len() == SIDE
+            && layers
+                .iter()
+                .all(|rows| rows.len() == SIDE && rows.iter().all(|row| row.len() == SIDE));
+        if !fits {
+            return Err(de::Error::custom("a qubic cube is 4 layers of 4 rows of 4"));
+        }
+        let mut cube = Cube::default();
+        for (z, rows) in layers.iter().enumerate() {
+            for (y, row) in rows.iter().enumerate() {
+                for (x, &cell) in row.iter().enumerate() {
+                    if cell == Cell::Blocked {
+                        return Err(de::Error::custom("qubic has no blocked squares"));
+                    }
+                    cube.set(x, y, z, cell);
+                }
+            }
+        }
+        Ok(cube)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct QubicGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// the cube as layers from `z = 0` up, each as rows of cells
+    pub layers: Cube,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl QubicGame {
+    /// the seat of `player_id` and the square `turn` is for, if they may play
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, usize, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let (x, y, z) = match turn.position.as_slice() {
+            [x, y, z] => (*x, *y, *z),
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= SIDE || y >= SIDE || z >= SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if self.layers.get(x, y, z) != Cell::Empty {
+            return Err(MoveError::Occupied);
+        }
+        Ok((seat, x, y, z))
+    }
+
+    fn get_outcome(&self) -> GameOutcome {
+        let winner = self
+            .layers
+            .completed_line()
+            .and_then(|(seat, cells)| Some((self.seating.player_in(seat)?, cells)));
+        match winner {
+            Some((player, cells)) => GameOutcome::Won { player, cells },
+            None if self.layers.empty_squares().is_empty() => GameOutcome::Draw,
+            None => GameOutcome::InProgress,
+        }
+    }
+}
+
+impl Game for QubicGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, x, y, z) = self.check_move(player_id, turn)?;
+        self.layers.set(x, y, z, Cell::Taken(seat));
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        self.outcome = self.get_outcome();
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        self.layers
+            .empty_squares()
+            .into_iter()
+            .map(|(x, y, z)| GameTurnMessage {
+                position: vec![x, y, z],
+            })
+            .collect()
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated() -> QubicGame {
+        let mut g = QubicGame::default();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut QubicGame, player_id: usize, position: Vec<usize>) -> Result<(), MoveError> {
+        g.apply_move(player_id, &GameTurnMessage { position })
+    }
+
+    #[test]
+    fn the_cube_has_76_lines() {
+        assert_eq!(build_lines().len(), 76);
+        assert!(build_lines().iter().all(|line| line.count_ones() == 4));
+    }
+
+    #[test]
+    fn a_line_through_the_layers_wins() {
+        let mut g = seated();
+        for z in 0..3 {
+            play(&mut g, 1, vec![1, 2, z]).unwrap();
+            play(&mut g, 2, vec![z, 0, 0]).unwrap();
+        }
+        play(&mut g, 1, vec![1, 2, 3]).unwrap();
+        assert_eq!(g.winner, Some(1));
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![1, 2, 0], vec![1, 2, 1], vec![1, 2, 2], vec![1, 2, 3]],
+            }
+        );
+    }
+
+    #[test]
+    fn the_corner_to_corner_diagonal_wins() {
+        let mut g = seated();
+        for i in 0..3 {
+            play(&mut g, 1, vec![3 - i, i, 3 - i]).unwrap();
+            play(&mut g, 2, vec![i, 0, 0]).unwrap();
+        }
+        play(&mut g, 1, vec![0, 3, 0]).unwrap();
+        assert_eq!(g.winner, Some(1));
+    }
+
+    #[test]
+    fn moves_need_three_coordinates() {
+        let mut g = seated();
+        assert_eq!(play(&mut g, 1, vec![0, 0]), Err(MoveError::Malformed));
+        assert_eq!(play(&mut g, 1, vec![0, 0, 4]), Err(MoveError::OutOfBounds));
+        play(&mut g, 1, vec![0, 0, 0]).unwrap();
+        assert_eq!(play(&mut g, 2, vec![0, 0, 0]), Err(MoveError::Occupied));
+        assert_eq!(g.legal_moves().len(), 63);
+    }
+
+    #[test]
+    fn state_is_a_layered_grid() {
+        let mut g = seated();
+        play(&mut g, 1, vec![3, 2, 1]).unwrap();
+        let json = g.state();
+        assert_eq!(json["layers"].as_array().unwrap().len(), 4);
+        assert_eq!(json["layers"][1][2][3], 1);
+        let back: QubicGame = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(back.layers, g.layers);
+
+        let mut blocked = json;
+        blocked["layers"][0][0][0] = serde_json::json!(3);
+        assert!(serde_json::from_value::<QubicGame>(blocked).is_err());
+    }
+}
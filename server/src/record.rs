@@ -0,0 +1,529 @@
+//! A PGN-like text record of a tic-tac-toe game, for sharing and archiving.
+//! Squares are named like spreadsheet cells: a letter for the column from the
+//! left and a number for the row from the top, so `a1` is the top left corner.
+//!
+//! ```text
+//! [Variant "tictactoe"]
+//! [Size "3x3"]
+//! [WinLength "3"]
+//! [Date "2019.04.01"]
+//! [X "player 1"]
+//! [O "player 2"]
+//! [Result "1-0"]
+//!
+//! 1. a1 b2 2. a2 c3 3. a3 1-0
+//! ```
+//!
+//! Games played with any of the optional `Rules` get a `[Rules "misere wild
+//! pie torus"]` header listing them. A wild move names the mark it placed,
+//! `b2o`, and taking the first move over under the pie rule is written
+//! `swap`. Squares blocked before the game started are listed in a
+//! `[Blocked "b2 c3"]` header. A game set up from a position rather than an
+//! empty board carries it in a `[Position "x2/1o1/3 x 3x3 3"]` header, and
+//! its moves are played on from there. If o moves first there, the moves
+//! start `1... b2 2. a1`, the way chess writes black moving first.
+
+use game::{Game, GameOutcome, GameTurnMessage, Seat};
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tictactoe::{Rules, TicTacToeGame, MAX_BOARD_SIZE};
+
+const COLUMNS: &str = "abcdefghijklmnop";
+
+/// the name of the square at column `x`, row `y`, e.g. `b3` for (1, 2)
+pub fn square_name(x: usize, y: usize) -> String {
+    let column = COLUMNS.chars().nth(x).unwrap_or('?');
+    format!("{}{}", column, y + 1)
+}
+
+/// the (x, y) of a square name like `b3`, `None` if it isn't one
+pub fn parse_square(name: &str) -> Option<(usize, usize)> {
+    let mut chars = name.chars();
+    let x = COLUMNS.find(chars.next()?)?;
+    let row: usize = chars.as_str().parse().ok()?;
+    if row == 0 || row > MAX_BOARD_SIZE {
+        return None;
+    }
+    Some((x, row - 1))
+}
+
+/// how a move's position is written, see the module docs
+pub fn move_name(position: &[usize]) -> String {
+    match position {
+        [] => "swap".to_owned(),
+        [x, y] => square_name(*x, *y),
+        [x, y, mark] => match Seat::from_number(*mark as u64) {
+            Some(mark) => format!("{}{}", square_name(*x, *y), mark.symbol()),
+            None => "?".to_owned(),
+        },
+        _ => "?".to_owned(),
+    }
+}
+
+/// the position of a move written by `move_name`, `None` if it isn't one
+pub fn parse_move(name: &str) -> Option<Vec<usize>> {
+    if name == "swap" {
+        return Some(Vec::new());
+    }
+    let (square, mark) = match name.chars().last()? {
+        'x' => (&name[..name.len() - 1], Some(Seat::One)),
+        'o' => (&name[..name.len() - 1], Some(Seat::Two)),
+        _ => (name, None),
+    };
+    let (x, y) = parse_square(square)?;
+    let mut position = vec![x, y];
+    position.extend(mark.map(|mark| mark.number() as usize));
+    Some(position)
+}
+
+/// the names of the rules that are on, in header order
+fn rule_names(rules: Rules) -> Vec<&'static str> {
+    let named = [
+        ("misere", rules.misere),
+        ("wild", rules.wild),
+        ("pie", rules.pie),
+        ("torus", rules.torus),
+    ];
+    named
+        .iter()
+        .filter(|&&(_, on)| on)
+        .map(|&(name, _)| name)
+        .collect()
+}
+
+/// today's date the way the `Date` header wants it, `YYYY.MM.DD`
+pub fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// turn days since 1970-01-01 into a calendar date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = if z >= 0 { z } else { z - 146_096 } / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// everything needed to write a game down and play it back later
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    pub variant: String,
+    pub width: usize,
+    pub height: usize,
+    pub win_length: usize,
+    pub date: String,
+    /// who played x, then who played o
+    pub players: Vec<String>,
+    /// `1-0` x won, `0-1` o won, `1/2-1/2` a draw and `*` still going
+    pub result: String,
+    pub rules: Rules,
+    /// the squares blocked before the first move
+    pub blocked: Vec<(usize, usize)>,
+    /// the position the game was set up from, `None` for an empty board
+    pub setup: Option<String>,
+    /// the position of every move, oldest first
+    pub moves: Vec<Vec<usize>>,
+}
+
+impl GameRecord {
+    /// write down `game` as it stands, players named by their seat ids
+    pub fn from_game(game: &TicTacToeGame) -> Self {
+        let name = |seat: Option<usize>| match seat {
+            Some(id) => format!("player {}", id),
+            None => "?".to_owned(),
+        };
+        let result = match game.outcome {
+            GameOutcome::InProgress => "*",
+            GameOutcome::Draw => "1/2-1/2",
+            GameOutcome::Won { player, .. } if Some(player) == game.seating.player1 => "1-0",
+            GameOutcome::Won { .. } => "0-1",
+        };
+        GameRecord {
+            variant: "tictactoe".to_owned(),
+            width: game.width,
+            height: game.height,
+            win_length: game.win_length,
+            date: "????.??.??".to_owned(),
+            players: vec![name(game.seating.player1), name(game.seating.player2)],
+            result: result.to_owned(),
+            rules: game.rules,
+            // a set up position already has its blocked squares in it
+            blocked: match game.setup {
+                Some(_) => Vec::new(),
+                None => game.grid.blocked().squares(),
+            },
+            setup: game.setup.clone(),
+            moves: game
+                .moves
+                .iter()
+                .map(|played| played.turn.position.clone())
+                .collect(),
+        }
+    }
+
+    /// play the moves back on a fresh board, or from the set up position,
+    /// with players 1 and 2 seated
+    pub fn to_game(&self) -> Result<TicTacToeGame, String> {
+        if self.variant != "tictactoe" {
+            return Err(format!("can't replay a {} game", self.variant));
+        }
+        let mut game = match self.setup {
+            Some(ref position) => TicTacToeGame::from_position(position)?,
+            None => TicTacToeGame::with_size(self.width, self.height, self.win_length)?,
+        };
+        game.rules = self.rules;
+        for &(x, y) in &self.blocked {
+            game.block(x, y)?;
+        }
+        game.add_player(1);
+        game.add_player(2);
+        for (number, position) in self.moves.iter().enumerate() {
+            let player = game.seating.current_player_turn.unwrap_or(1);
+            let turn = GameTurnMessage {
+                position: position.clone(),
+            };
+            game.apply_move(player, &turn).map_err(|e| {
+                format!(
+                    "move {} ({}) is illegal: {}",
+                    number + 1,
+                    move_name(position),
+                    e
+                )
+            })?;
+        }
+        Ok(game)
+    }
+
+    /// how many seats along from the first the first move was made, which
+    /// only a set up position can change
+    fn first_seat_to_move(&self) -> usize {
+        let side = self
+            .setup
+            .as_ref()
+            .and_then(|setup| setup.split_whitespace().nth(1));
+        match side {
+            Some("o") => 1,
+            _ => 0,
+        }
+    }
+
+    /// read a record back from the text `Display` writes
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut record = GameRecord {
+            variant: "tictactoe".to_owned(),
+            width: 3,
+            height: 3,
+            win_length: 3,
+            date: "????.??.??".to_owned(),
+            players: vec!["?".to_owned(), "?".to_owned()],
+            result: "*".to_owned(),
+            rules: Rules::default(),
+            blocked: Vec::new(),
+            setup: None,
+            moves: Vec::new(),
+        };
+        let mut movetext = String::new();
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') {
+                let (key, value) = parse_tag(line)?;
+                match key {
+                    "Variant" => record.variant = value.to_owned(),
+                    "Size" => {
+                        let mut sides = value.split('x').map(|side| side.parse().ok());
+                        match (sides.next(), sides.next(), sides.next()) {
+                            (Some(Some(width)), Some(Some(height)), None) => {
+                                record.width = width;
+                                record.height = height;
+                            }
+                            _ => return Err(format!("bad board size {:?}", value)),
+                        }
+                    }
+                    "WinLength" => {
+                        record.win_length = value
+                            .parse()
+                            .map_err(|_| format!("bad win length {:?}", value))?
+                    }
+                    "Date" => record.date = value.to_owned(),
+                    "X" => record.players[0] = value.to_owned(),
+                    "O" => record.players[1] = value.to_owned(),
+                    "Result" => record.result = value.to_owned(),
+                    "Rules" => {
+                        for name in value.split_whitespace() {
+                            match name {
+                                "misere" => record.rules.misere = true,
+                                "wild" => record.rules.wild = true,
+                                "pie" => record.rules.pie = true,
+                                "torus" => record.rules.torus = true,
+                                other => return Err(format!("unknown rule {:?}", other)),
+                            }
+                        }
+                    }
+                    "Blocked" => {
+                        for name in value.split_whitespace() {
+                            let square = parse_square(name)
+                                .ok_or_else(|| format!("bad blocked square {:?}", name))?;
+                            record.blocked.push(square);
+                        }
+                    }
+                    "Position" => record.setup = Some(value.to_owned()),
+                    // unknown tags are kept by other tools, we just skip them
+                    _ => {}
+                }
+            } else {
+                movetext.push_str(line);
+                movetext.push(' ');
+            }
+        }
+        for token in movetext.split_whitespace() {
+            if token.ends_with('.') || is_result(token) {
+                continue;
+            }
+            let position = parse_move(token).ok_or_else(|| format!("bad move {:?}", token))?;
+            record.moves.push(position);
+        }
+        Ok(record)
+    }
+}
+
+/// a download file name for the record of `room`. anything but letters,
+/// digits, `-` and `_` becomes `_`, so the name is safe to put in a header
+pub fn file_name(room: &str) -> String {
+    let stem: String = room
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if stem.is_empty() {
+        "game.pgn".to_owned()
+    } else {
+        format!("{}.pgn", stem)
+    }
+}
+
+fn is_result(token: &str) -> bool {
+    ["1-0", "0-1", "1/2-1/2", "*"].contains(&token)
+}
+
+/// split `[Key "value"]` into its key and value
+fn parse_tag(line: &str) -> Result<(&str, &str), String> {
+    let inner = line.trim_start_matches('[').trim_end_matches(']').trim();
+    let space = inner
+        .find(' ')
+        .ok_or_else(|| format!("bad header {:?}", line))?;
+    let (key, value) = inner.split_at(space);
+    let value = value.trim();
+    if value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(format!("bad header {:?}", line));
+    }
+    Ok((key, &value[1..value.len() - 1]))
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "[Variant \"{}\"]", self.variant)?;
+        writeln!(f, "[Size \"{}x{}\"]", self.width, self.height)?;
+        writeln!(f, "[WinLength \"{}\"]", self.win_length)?;
+        writeln!(f, "[Date \"{}\"]", self.date)?;
+        writeln!(f, "[X \"{}\"]", self.players[0])?;
+        writeln!(f, "[O \"{}\"]", self.players[1])?;
+        writeln!(f, "[Result \"{}\"]", self.result)?;
+        let rules = rule_names(self.rules);
+        if !rules.is_empty() {
+            writeln!(f, "[Rules \"{}\"]", rules.join(" "))?;
+        }
+        if !self.blocked.is_empty() {
+            let names: Vec<String> = self
+                .blocked
+                .iter()
+                .map(|&(x, y)| square_name(x, y))
+                .collect();
+            writeln!(f, "[Blocked \"{}\"]", names.join(" "))?;
+        }
+        if let Some(ref setup) = self.setup {
+            writeln!(f, "[Position \"{}\"]", setup)?;
+        }
+        writeln!(f)?;
+        // a round is one move from each seat. a set up position can leave
+        // someone later in the round to move first, like `1...` in chess
+        let seats = self.players.len().max(1);
+        let skipped = self.first_seat_to_move() % seats;
+        if skipped > 0 && !self.moves.is_empty() {
+            write!(f, "1... ")?;
+        }
+        for (i, position) in self.moves.iter().enumerate() {
+            let (round, seat) = ((i + skipped) / seats, (i + skipped) % seats);
+            if seat == 0 {
+                write!(f, "{}. ", round + 1)?;
+            }
+            write!(f, "{} ", move_name(position))?;
+        }
+        writeln!(f, "{}", self.result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::Cell;
+
+    #[test]
+    fn squares_are_named_like_spreadsheet_cells() {
+        assert_eq!(square_name(0, 0), "a1");
+        assert_eq!(square_name(1, 2), "b3");
+        assert_eq!(square_name(15, 15), "p16");
+        assert_eq!(parse_square("b3"), Some((1, 2)));
+        assert_eq!(parse_square("p16"), Some((15, 15)));
+        assert_eq!(parse_square("a0"), None);
+        assert_eq!(parse_square("z1"), None);
+        assert_eq!(parse_square("b"), None);
+    }
+
+    #[test]
+    fn dates_come_out_in_the_calendar() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(17_987), (2019, 4, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn records_round_trip_through_text() {
+        let mut game = TicTacToeGame::new();
+        game.add_player(1);
+        game.add_player(2);
+        for &(player, x, y) in &[(1, 0, 0), (2, 1, 1), (1, 0, 1), (2, 2, 2), (1, 0, 2)] {
+            let turn = GameTurnMessage {
+                position: vec![x, y],
+            };
+            game.apply_move(player, &turn).unwrap();
+        }
+        let mut record = GameRecord::from_game(&game);
+        record.date = "2019.04.01".to_owned();
+        let text = record.to_string();
+        assert!(text.contains("[Result \"1-0\"]"));
+        assert!(text.ends_with("1. a1 b2 2. a2 c3 3. a3 1-0\n"));
+
+        let parsed = GameRecord::parse(&text).unwrap();
+        assert_eq!(parsed, record);
+        let replayed = parsed.to_game().unwrap();
+        assert_eq!(replayed.grid, game.grid);
+        assert_eq!(replayed.outcome.winner(), Some(1));
+    }
+
+    #[test]
+    fn bad_records_are_refused() {
+        assert!(GameRecord::parse("[Size \"3\"]\n\n1. a1 *").is_err());
+        assert!(GameRecord::parse("1. a1 zz *").is_err());
+        let record = GameRecord::parse("1. a1 a1 *").unwrap();
+        assert!(record.to_game().is_err());
+    }
+
+    #[test]
+    fn rules_and_their_moves_are_written_down() {
+        assert_eq!(move_name(&[1, 2, 2]), "b3o");
+        assert_eq!(parse_move("b3o"), Some(vec![1, 2, 2]));
+        assert_eq!(parse_move("swap"), Some(Vec::new()));
+        assert_eq!(parse_move("b3q"), None);
+
+        let mut game = TicTacToeGame::new();
+        game.rules = Rules {
+            wild: true,
+            pie: true,
+            ..Rules::default()
+        };
+        game.add_player(1);
+        game.add_player(2);
+        for &(player, ref position) in &[(1, vec![1, 1, 2]), (2, vec![]), (1, vec![0, 0, 1])] {
+            let turn = GameTurnMessage {
+                position: position.clone(),
+            };
+            game.apply_move(player, &turn).unwrap();
+        }
+        let text = GameRecord::from_game(&game).to_string();
+        assert!(text.contains("[Rules \"wild pie\"]"));
+        assert!(text.contains("1. b2o swap 2. a1x *"));
+        let replayed = GameRecord::parse(&text).unwrap().to_game().unwrap();
+        assert_eq!(replayed.grid, game.grid);
+        assert_eq!(replayed.rules, game.rules);
+    }
+
+    #[test]
+    fn blocked_squares_and_the_torus_are_written_down() {
+        let mut game = TicTacToeGame::new();
+        game.rules.torus = true;
+        game.block(1, 1).unwrap();
+        game.block(2, 0).unwrap();
+        game.add_player(1);
+        game.add_player(2);
+        let turn = GameTurnMessage {
+            position: vec![0, 0],
+        };
+        game.apply_move(1, &turn).unwrap();
+        let text = GameRecord::from_game(&game).to_string();
+        assert!(text.contains("[Rules \"torus\"]"));
+        assert!(text.contains("[Blocked \"c1 b2\"]"));
+        let replayed = GameRecord::parse(&text).unwrap().to_game().unwrap();
+        assert_eq!(replayed.grid, game.grid);
+        assert_eq!(replayed.grid.get(1, 1), Cell::Blocked);
+        assert!(GameRecord::parse("[Blocked \"b2 zz\"]\n\n*").is_err());
+    }
+
+    #[test]
+    fn set_up_positions_are_written_down() {
+        let mut game = TicTacToeGame::from_position("x2/1o1/3 x 3x3 3").unwrap();
+        game.block(2, 2).unwrap();
+        game.add_player(1);
+        game.add_player(2);
+        let turn = GameTurnMessage {
+            position: vec![0, 1],
+        };
+        game.apply_move(1, &turn).unwrap();
+        let text = GameRecord::from_game(&game).to_string();
+        assert!(text.contains("[Position \"x2/1o1/2# x 3x3 3\"]"));
+        assert!(!text.contains("[Blocked"));
+        assert!(text.ends_with("1. a2 *\n"));
+        let replayed = GameRecord::parse(&text).unwrap().to_game().unwrap();
+        assert_eq!(replayed.grid, game.grid);
+        assert_eq!(replayed.current_player(), Some(2));
+    }
+
+    #[test]
+    fn moves_are_numbered_from_whoever_the_set_up_has_moving() {
+        let mut game = TicTacToeGame::from_position("x2/3/3 o 3x3 3").unwrap();
+        game.add_player(1);
+        game.add_player(2);
+        for &(player, x, y) in &[(2, 1, 1), (1, 0, 1), (2, 2, 2)] {
+            let turn = GameTurnMessage {
+                position: vec![x, y],
+            };
+            game.apply_move(player, &turn).unwrap();
+        }
+        let text = GameRecord::from_game(&game).to_string();
+        assert!(text.ends_with("\n1... b2 2. a2 c3 *\n"), "{}", text);
+        let replayed = GameRecord::parse(&text).unwrap().to_game().unwrap();
+        assert_eq!(replayed.grid, game.grid);
+    }
+
+    #[test]
+    fn file_names_keep_to_safe_characters() {
+        assert_eq!(file_name("room-1_b"), "room-1_b.pgn");
+        assert_eq!(file_name("a\"; b=\u{7}c"), "a___b__c.pgn");
+        assert_eq!(file_name(""), "game.pgn");
+    }
+}
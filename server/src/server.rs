@@ -3,9 +3,30 @@
 //! room through `ChatServer`.
 
 use actix::prelude::*;
+use ai;
+use clock::{ClockOptions, GameClock};
+use connect_four::ConnectFourGame;
+use game::{Game, GameOutcome, GameTurnMessage, MoveError};
+use gomoku::{GomokuGame, GomokuRules};
+use multiplayer::MultiplayerGame;
+use notakto::NotaktoGame;
+use numerical::NumericalGame;
+use order_chaos::OrderChaosGame;
+use quantum::QuantumGame;
+use qubic::QubicGame;
 use rand::{self, rngs::ThreadRng, Rng};
+use record;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tictactoe::{Rules, TicTacToeGame};
+use ultimate::UltimateGame;
+
+/// how often the game clocks are checked for a flag fall
+const CLOCK_TICK: Duration = Duration::from_millis(100);
+
+/// how many threads computer players think on, away from the rooms
+const BOT_THREADS: usize = 2;
 
 #[derive(Debug, Serialize, Deserialize, Message)]
 pub struct JsonGeneralMessage {
@@ -13,58 +34,11 @@ pub struct JsonGeneralMessage {
     pub data: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Message)]
-pub struct TicTacToeGame {
-    pub player1: Option<usize>,
-    pub player2: Option<usize>,
-    pub current_player_turn: Option<usize>,
-    pub grid: Vec<Vec<usize>>,
-    pub winner: Option<usize>,
-}
-
-impl TicTacToeGame {
-    pub fn new() -> Self {
-        TicTacToeGame {
-            player1: None,
-            player2: None,
-            current_player_turn: None,
-            grid: vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 0, 0]],
-            winner: None,
-        }
-    }
-
-    pub fn add_player(&mut self, id: usize) {
-        // make the joiner a player
-        if self.player1.is_none() {
-            self.player1 = Some(id);
-        } else if self.player2.is_none() {
-            self.player2 = Some(id);
-        }
-        // set someone to have a turn
-        match (self.player1, self.player2) {
-            (Some(a), Some(b)) => {
-                // TODO: set whichever player has fewer moves as currently going
-                self.current_player_turn = Some(a);
-            }
-            (_, _) => {
-                println!("one of the players was not set, nobody's turn first");
-            }
-        }
-    }
-
-    pub fn remove_player(&mut self, id: usize) {
-        if self.player1 == Some(id) {
-            self.player1 = None;
-        } else if self.player2 == Some(id) {
-            self.player2 = None;
-        }
-    }
-}
-
 #[derive(Debug, Message)]
 pub enum GameMessage {
     Chat(ChatMessage),
-    Turn(GameStateMessage),
+    Turn(Box<GameStateMessage>),
+    Error(ErrorMessage),
 }
 
 /// Chat server sends this messages to session
@@ -80,7 +54,98 @@ pub struct ChatMessage {
 pub struct GameStateMessage {
     pub player_id: usize,
     pub event_type: String,
-    pub content: TicTacToeGame,
+    pub content: serde_json::Value,
+    /// the running score, if the room is playing a best-of-N match
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub match_score: Option<MatchScore>,
+    /// each player's time left, if the room plays with a clock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<GameClock>,
+    /// the board as a position string, to share or start a new room from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<String>,
+}
+
+/// the longest match a room can be made for
+pub const MAX_BEST_OF: usize = 99;
+
+/// a best-of-N series of games between the same players
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchScore {
+    pub best_of: usize,
+    pub games_played: usize,
+    /// games won by each player id
+    pub wins: HashMap<usize, usize>,
+    pub draws: usize,
+    /// set once the match is decided, `None` in a drawn match
+    pub winner: Option<usize>,
+    pub finished: bool,
+}
+
+impl MatchScore {
+    pub fn new(best_of: usize) -> Self {
+        MatchScore {
+            best_of,
+            games_played: 0,
+            wins: HashMap::new(),
+            draws: 0,
+            winner: None,
+            finished: false,
+        }
+    }
+
+    /// count a finished game. a match is won by winning a majority of the
+    /// games, otherwise it ends when they run out and most wins takes it
+    pub fn record(&mut self, outcome: &GameOutcome) {
+        match outcome {
+            GameOutcome::InProgress => return,
+            GameOutcome::Won { player, .. } => *self.wins.entry(*player).or_insert(0) += 1,
+            GameOutcome::Draw => self.draws += 1,
+        }
+        self.games_played += 1;
+
+        let majority = self.best_of / 2 + 1;
+        if let Some((&player, _)) = self.wins.iter().find(|(_, &won)| won >= majority) {
+            self.winner = Some(player);
+            self.finished = true;
+        } else if self.games_played >= self.best_of {
+            let most = self.wins.values().cloned().max().unwrap_or(0);
+            let leaders: Vec<usize> = self
+                .wins
+                .iter()
+                .filter(|(_, &won)| won == most)
+                .map(|(&player, _)| player)
+                .collect();
+            if leaders.len() == 1 {
+                self.winner = Some(leaders[0]);
+            }
+            self.finished = true;
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut wins: Vec<String> = self
+            .wins
+            .iter()
+            .map(|(player, won)| format!("player {}: {}", player, won))
+            .collect();
+        wins.sort();
+        wins.push(format!("draws: {}", self.draws));
+        format!(
+            "after {} of {} games, {}",
+            self.games_played,
+            self.best_of,
+            wins.join(", ")
+        )
+    }
+}
+
+/// sent only to the session whose request was refused
+#[derive(Debug, Serialize, Deserialize, Message)]
+pub struct ErrorMessage {
+    pub event_type: String,
+    pub error: MoveError,
+    pub content: String,
 }
 
 /// Message for chat server communications
@@ -118,13 +183,241 @@ impl actix::Message for ListRooms {
     type Result = Vec<String>;
 }
 
+/// The room's current game written down as a record, `None` if there is no
+/// such room or its variant has no notation
+pub struct GetRecord {
+    pub room: String,
+}
+
+impl actix::Message for GetRecord {
+    type Result = Option<String>;
+}
+
+/// A computer player's turn to work out, sent to a `BotWorker`
+struct Think {
+    room: String,
+    bot_id: usize,
+    /// which search this is, see `ChatRoom::thinking`
+    search: usize,
+    game: Box<dyn Game>,
+    difficulty: ai::Difficulty,
+    reply_to: Recipient<BotMove>,
+}
+
+impl actix::Message for Think {
+    type Result = ();
+}
+
+/// The move a computer player settled on, `None` if it had nothing to play
+#[derive(Message)]
+pub struct BotMove {
+    room: String,
+    bot_id: usize,
+    search: usize,
+    turn: Option<GameTurnMessage>,
+}
+
+/// Works out computer players' moves on its own threads, so a long search
+/// doesn't hold up every room and clock on the server
+pub struct BotWorker;
+
+impl Actor for BotWorker {
+    type Context = SyncContext<Self>;
+}
+
+impl Handler<Think> for BotWorker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Think, _: &mut SyncContext<Self>) {
+        let turn = ai::choose_move(&*msg.game, msg.difficulty, &mut rand::thread_rng());
+        let _ = msg.reply_to.do_send(BotMove {
+            room: msg.room,
+            bot_id: msg.bot_id,
+            search: msg.search,
+            turn,
+        });
+    }
+}
+
 /// Join room, if room does not exists create new one.
 #[derive(Message)]
+#[rtype(result = "Result<(), String>")]
 pub struct Join {
     /// Client id
     pub id: usize,
     /// Room name
     pub name: String,
+    /// Board to use if the room has to be created
+    pub options: GameOptions,
+}
+
+/// what a client sends as the data of a "join" event
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JoinRoomMessage {
+    pub room: String,
+    #[serde(default)]
+    pub options: GameOptions,
+}
+
+/// settings for a newly created room, classic 3x3 tic-tac-toe by default
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameOptions {
+    /// which game the room plays, "tictactoe", "ultimate", "qubic",
+    /// "connect_four", "numerical", "order_chaos", "notakto", "quantum" or
+    /// "gomoku"
+    pub variant: String,
+    /// how many play, two if it's left out. only tic-tac-toe takes more
+    pub players: Option<usize>,
+    /// board size and win length, the variant's usual ones if left out. only
+    /// tic-tac-toe and connect four take them
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub win_length: Option<usize>,
+    /// play a series of this many games, keeping score, instead of just one
+    pub best_of: Option<usize>,
+    /// time control, no clock at all if it's missing
+    pub clock: Option<ClockOptions>,
+    /// start from this position string instead of an empty board, its
+    /// size wins over `width`, `height` and `win_length`
+    pub position: Option<String>,
+    /// misère, wild, the pie rule and the torus, only for plain tic-tac-toe
+    pub rules: Rules,
+    /// `[x, y]` squares to block before the game starts, tic-tac-toe only
+    pub blocked: Vec<(usize, usize)>,
+    /// how many more squares to block, picked at random
+    pub random_blocked: usize,
+    /// how many boards notakto is played on, one if it's left out
+    pub boards: Option<usize>,
+    /// exact five, renju and swap2, only for gomoku
+    pub gomoku_rules: GomokuRules,
+}
+
+impl Default for GameOptions {
+    fn default() -> Self {
+        GameOptions {
+            variant: "tictactoe".to_owned(),
+            players: None,
+            width: None,
+            height: None,
+            win_length: None,
+            best_of: None,
+            clock: None,
+            position: None,
+            rules: Rules::default(),
+            blocked: Vec::new(),
+            random_blocked: 0,
+            boards: None,
+            gomoku_rules: GomokuRules::default(),
+        }
+    }
+}
+
+impl GameOptions {
+    pub fn new_game(&self) -> Result<Box<dyn Game>, String> {
+        match self.best_of {
+            Some(0) => return Err("a match needs at least one game".to_owned()),
+            Some(games) if games > MAX_BEST_OF => {
+                return Err(format!("a match is at most {} games", MAX_BEST_OF))
+            }
+            _ => {}
+        }
+        if let Some(ClockOptions {
+            initial_seconds: 0, ..
+        }) = self.clock
+        {
+            return Err("a clock needs some time on it".to_owned());
+        }
+        let players = self.players.unwrap_or(2);
+        if self.variant != "tictactoe" && players != 2 {
+            return Err(format!("{} games are for two players", self.variant));
+        }
+        let sized = ["tictactoe", "connect_four"].contains(&self.variant.as_str());
+        if !sized && (self.width.is_some() || self.height.is_some() || self.win_length.is_some()) {
+            return Err(format!("{} games have a fixed board size", self.variant));
+        }
+        if self.variant != "tictactoe" && self.rules != Rules::default() {
+            return Err(format!("{} games don't take rule options", self.variant));
+        }
+        if self.variant != "tictactoe" && (!self.blocked.is_empty() || self.random_blocked > 0) {
+            return Err(format!("{} games can't have blocked squares", self.variant));
+        }
+        if self.variant != "notakto" && self.boards.is_some() {
+            return Err(format!(
+                "{} games don't take a number of boards",
+                self.variant
+            ));
+        }
+        if self.variant != "gomoku" && self.gomoku_rules != GomokuRules::default() {
+            return Err(format!("{} games don't take gomoku rules", self.variant));
+        }
+        match (self.variant.as_ref(), &self.position) {
+            ("tictactoe", None) if players != 2 => {
+                if self.rules != Rules::default()
+                    || !self.blocked.is_empty()
+                    || self.random_blocked > 0
+                {
+                    return Err("rules and blocked squares are only for two players".to_owned());
+                }
+                let game = MultiplayerGame::with_size(
+                    players,
+                    self.width.unwrap_or(3),
+                    self.height.unwrap_or(3),
+                    self.win_length.unwrap_or(3),
+                )?;
+                Ok(Box::new(game))
+            }
+            ("tictactoe", Some(_)) if players != 2 => {
+                Err("games for more than two players can't start from a position".to_owned())
+            }
+            ("tictactoe", position) => {
+                let mut game = match position {
+                    Some(position) => TicTacToeGame::from_position(position)?,
+                    None => TicTacToeGame::with_size(
+                        self.width.unwrap_or(3),
+                        self.height.unwrap_or(3),
+                        self.win_length.unwrap_or(3),
+                    )?,
+                };
+                game.rules = self.rules;
+                for &(x, y) in &self.blocked {
+                    game.block(x, y)?;
+                }
+                game.block_random(self.random_blocked, &mut rand::thread_rng())?;
+                Ok(Box::new(game))
+            }
+            ("ultimate", None) => Ok(Box::new(UltimateGame::new())),
+            ("ultimate", Some(_)) => Err("ultimate games can't start from a position".to_owned()),
+            ("qubic", None) => Ok(Box::new(QubicGame::default())),
+            ("qubic", Some(_)) => Err("qubic games can't start from a position".to_owned()),
+            ("connect_four", None) => {
+                let game = ConnectFourGame::with_size(
+                    self.width.unwrap_or(7),
+                    self.height.unwrap_or(6),
+                    self.win_length.unwrap_or(4),
+                )?;
+                Ok(Box::new(game))
+            }
+            ("connect_four", Some(_)) => {
+                Err("connect four games can't start from a position".to_owned())
+            }
+            ("numerical", None) => Ok(Box::new(NumericalGame::new())),
+            ("numerical", Some(_)) => Err("numerical games can't start from a position".to_owned()),
+            ("order_chaos", None) => Ok(Box::new(OrderChaosGame::new())),
+            ("order_chaos", Some(_)) => {
+                Err("order and chaos games can't start from a position".to_owned())
+            }
+            ("notakto", None) => Ok(Box::new(NotaktoGame::with_boards(
+                self.boards.unwrap_or(1),
+            )?)),
+            ("notakto", Some(_)) => Err("notakto games can't start from a position".to_owned()),
+            ("quantum", None) => Ok(Box::new(QuantumGame::new())),
+            ("quantum", Some(_)) => Err("quantum games can't start from a position".to_owned()),
+            ("gomoku", None) => Ok(Box::new(GomokuGame::new(self.gomoku_rules))),
+            ("gomoku", Some(_)) => Err("gomoku games can't start from a position".to_owned()),
+            (other, _) => Err(format!("unknown variant {}", other)),
+        }
+    }
 }
 
 /// `ChatServer` manages chat rooms and responsible for coordinating chat
@@ -133,22 +426,154 @@ pub struct ChatServer {
     sessions: HashMap<usize, Recipient<GameMessage>>,
     rooms: HashMap<String, ChatRoom>,
     rng: ThreadRng,
+    /// where computer players think, `None` until the actor has started
+    bot_worker: Option<Addr<BotWorker>>,
+    /// where their moves come back to
+    bot_replies: Option<Recipient<BotMove>>,
+    /// how many searches have been started, to number the next one
+    searches: usize,
 }
 
 struct ChatRoom {
     sessions_subscribed_to_room: HashSet<usize>,
     message_count: usize,
-    game_state: TicTacToeGame,
+    game_state: Box<dyn Game>,
+    /// seated players that are computer players, and how well they play
+    bots: HashMap<usize, ai::Difficulty>,
+    /// who made each move and the game as it was just before it, oldest first
+    history: Vec<(usize, Box<dyn Game>)>,
+    pending_takeback: Option<TakebackRequest>,
+    /// who asked for a rematch their opponent hasn't accepted yet
+    pending_rematch: Option<usize>,
+    /// what the room was created with, so the next game is the same kind
+    options: GameOptions,
+    match_score: Option<MatchScore>,
+    clock: Option<GameClock>,
+    /// whoever created the room, the only one who can set its position
+    owner: Option<usize>,
+    /// the search a computer player is thinking over, if one is. a move that
+    /// comes back from any other search is for a position that has changed
+    thinking: Option<usize>,
+}
+
+/// a takeback waiting on the opponent to accept it
+struct TakebackRequest {
+    requested_by: usize,
+    /// how many moves get rolled back
+    moves: usize,
 }
 
 impl ChatRoom {
     pub fn new() -> Self {
-        ChatRoom {
+        ChatRoom::with_options(GameOptions::default()).expect("the default game is always valid")
+    }
+
+    pub fn with_options(options: GameOptions) -> Result<Self, String> {
+        Ok(ChatRoom {
             sessions_subscribed_to_room: HashSet::new(),
             message_count: 0,
-            game_state: TicTacToeGame::new(),
+            game_state: options.new_game()?,
+            bots: HashMap::new(),
+            history: Vec::new(),
+            pending_takeback: None,
+            pending_rematch: None,
+            match_score: options.best_of.map(MatchScore::new),
+            clock: options
+                .clock
+                .as_ref()
+                .map(|clock| GameClock::new(clock, Instant::now())),
+            owner: None,
+            thinking: None,
+            options,
+        })
+    }
+
+    /// start a fresh game with everyone still seated, each one seat along so
+    /// a different player moves first
+    fn next_game(&mut self) {
+        let game = match self.options.new_game() {
+            Ok(game) => game,
+            Err(e) => {
+                println!("the room's options stopped making a game: {}", e);
+                return;
+            }
+        };
+        let mut seats = self.game_state.seats();
+        seats.rotate_left(1);
+        self.replace_game(game, seats);
+    }
+
+    /// play `position` from here on, everyone keeping their seat
+    fn set_position(&mut self, position: &str) -> Result<(), String> {
+        let options = GameOptions {
+            position: Some(position.trim().to_owned()),
+            ..self.options.clone()
+        };
+        let game = options.new_game()?;
+        let seats = self.game_state.seats();
+        self.replace_game(game, seats);
+        Ok(())
+    }
+
+    /// seat the players on `game` and start it with a clean slate
+    fn replace_game(&mut self, mut game: Box<dyn Game>, seats: Vec<Option<usize>>) {
+        for id in seats.into_iter().flatten() {
+            game.add_player(id);
+        }
+        self.game_state = game;
+        self.history.clear();
+        self.thinking = None;
+        self.pending_takeback = None;
+        self.pending_rematch = None;
+        if let Some(clock) = self.clock.as_mut() {
+            clock.reset(Instant::now());
         }
     }
+
+    /// bring the clock up to date and start whoever's turn it is now. the
+    /// game decides when nobody can move, e.g. a two player game waiting for
+    /// someone to come back, and every clock stops until somebody can
+    fn sync_clock(&mut self, now: Instant) {
+        let seats = self.game_state.seats();
+        let running = self.game_state.current_player();
+        if let Some(clock) = self.clock.as_mut() {
+            clock.switch_to(&seats, running, now);
+        }
+    }
+
+    /// whether everyone seated apart from `player_id` is a computer player,
+    /// so only bots are left to answer what they ask for
+    fn only_bots_against(&self, player_id: usize) -> bool {
+        let others: Vec<usize> = self
+            .game_state
+            .seats()
+            .into_iter()
+            .flatten()
+            .filter(|&id| id != player_id)
+            .collect();
+        !others.is_empty() && others.iter().all(|id| self.bots.contains_key(id))
+    }
+
+    /// how many moves to roll back so `player_id` can replay their last one:
+    /// just theirs if nobody has answered it, or theirs and the replies
+    fn moves_to_take_back(&self, player_id: usize) -> Option<usize> {
+        self.history
+            .iter()
+            .rev()
+            .take(self.game_state.seat_count())
+            .position(|(mover, _)| *mover == player_id)
+            .map(|i| i + 1)
+    }
+
+    fn take_back(&mut self, moves: usize) {
+        for _ in 0..moves {
+            if let Some((_, before)) = self.history.pop() {
+                self.game_state = before;
+            }
+        }
+        self.thinking = None;
+        self.sync_clock(Instant::now());
+    }
 }
 
 impl Default for ChatServer {
@@ -161,155 +586,437 @@ impl Default for ChatServer {
             sessions: HashMap::new(),
             rooms: rooms,
             rng: rand::thread_rng(),
+            bot_worker: None,
+            bot_replies: None,
+            searches: 0,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Message)]
-pub struct GameTurnMessage {
-    position: Vec<usize>,
-}
-
-// TODO: i'm clearly calling it below
-#[allow(dead_code)]
-fn advance_turn(player_id: usize, cm: GameTurnMessage, game_state: TicTacToeGame) -> TicTacToeGame {
-    let mut new_game_state = match &game_state.current_player_turn {
-        Some(current) if current == &player_id => {
-            let mut gm = game_state.clone();
-            let x = cm.position[0];
-            let y = cm.position[1];
-            let symbol = match (game_state.player1, game_state.player2) {
-                (Some(a), Some(_)) if player_id == a => 1,
-                (Some(_), Some(b)) if player_id == b => 2,
-                (_, _) => {
-                    panic!("i dont even know");
+impl ChatServer {
+    fn send_turn(&mut self, room_name: &str, message: &str, player_id: usize) {
+        println!("sending the turn now");
+        let gameturn: GameTurnMessage = match serde_json::from_str(message) {
+            Ok(gameturn) => gameturn,
+            Err(e) => {
+                let detail = format!("{}: {}", MoveError::Malformed, e);
+                self.send_error(player_id, MoveError::Malformed, &detail);
+                return;
+            }
+        };
+        if let Err(e) = self.play_move(room_name, player_id, &gameturn) {
+            self.send_error(player_id, e, &e.to_string());
+            return;
+        }
+        self.play_bot_turns(room_name);
+    }
+
+    /// apply a move for a human or a bot and tell the room about it
+    fn play_move(
+        &mut self,
+        room_name: &str,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(), MoveError> {
+        let now = Instant::now();
+        let outcome = match self.rooms.get_mut(room_name) {
+            Some(room) => {
+                let before = room.game_state.clone();
+                room.game_state.apply_move(player_id, turn)?;
+                room.history.push((player_id, before));
+                room.pending_takeback = None;
+                if let Some(clock) = room.clock.as_mut() {
+                    clock.moved(player_id, now);
                 }
+                room.sync_clock(now);
+                if let Some(position) = room.game_state.position() {
+                    println!("{}: {}", room_name, position);
+                }
+                room.game_state.outcome()
+            }
+            None => return Ok(()),
+        };
+        self.after_turn(room_name, outcome);
+        Ok(())
+    }
+
+    /// tell the room how the game stands after anything that changed it
+    fn after_turn(&mut self, room_name: &str, outcome: GameOutcome) {
+        if let Some(score) = self
+            .rooms
+            .get_mut(room_name)
+            .and_then(|room| room.match_score.as_mut())
+        {
+            score.record(&outcome);
+        }
+        self.send_board(room_name);
+        match outcome {
+            GameOutcome::InProgress => return,
+            GameOutcome::Won { player, .. } => {
+                let announcement = format!("game over, player {} won", player);
+                self.send_chat(room_name, &announcement, 0);
+            }
+            GameOutcome::Draw => self.send_chat(room_name, "game over, it's a draw", 0),
+        }
+        self.continue_match(room_name);
+    }
+
+    /// run every room's clock and end the games of anyone out of time
+    fn tick_clocks(&mut self) {
+        let now = Instant::now();
+        let mut flagged = Vec::new();
+        for (name, room) in &mut self.rooms {
+            room.sync_clock(now);
+            let loser = room.clock.as_ref().and_then(|clock| clock.flagged());
+            if let Some(loser) = loser {
+                room.game_state.forfeit(loser);
+                room.sync_clock(now);
+                flagged.push((name.to_owned(), loser, room.game_state.outcome()));
+            }
+        }
+        for (room_name, loser, outcome) in flagged {
+            let announcement = format!("player {} ran out of time", loser);
+            self.send_chat(&room_name, &announcement, 0);
+            self.after_turn(&room_name, outcome);
+            self.play_bot_turns(&room_name);
+        }
+    }
+
+    /// after a game in a match, announce the score and set up the next game
+    fn continue_match(&mut self, room_name: &str) {
+        let score = match self.rooms.get_mut(room_name) {
+            Some(room) => match room.match_score.clone() {
+                Some(score) => {
+                    if !score.finished {
+                        room.next_game();
+                    }
+                    score
+                }
+                None => return,
+            },
+            None => return,
+        };
+        self.send_chat(room_name, &score.describe(), 0);
+        if score.finished {
+            let announcement = match score.winner {
+                Some(player) => format!("player {} wins the match!", player),
+                None => "the match is a draw".to_owned(),
             };
-            gm.grid[y][x] = symbol;
-            // advance the 'current player' state
-            let next_player = match (gm.player1, gm.player2, gm.current_player_turn) {
-                (Some(one), Some(two), Some(current)) if current == two => Some(one),
-                (Some(one), Some(two), Some(current)) if current == one => Some(two),
-                (a, b, c) => panic!("how could it not match {:?},{:?},{:?}", a, b, c),
-            };
-            gm.current_player_turn = next_player;
-            gm
-        }
-        Some(other_id) => {
-            println!("not your turn, {}", player_id);
-            game_state.clone()
-        }
-        None => {
-            println!("this game has not even been initialized");
-            game_state.clone()
-        }
-    };
-    new_game_state.winner = get_winner(&new_game_state);
-    new_game_state
-}
-
-/// if the 3 components of the triple match, return a Some of it, else None
-fn get_winner_of_triple(triple: Vec<usize>, game_state: &TicTacToeGame) -> Option<usize> {
-    let matching_triple = match triple.as_slice() {
-        [x, y, z] if x == y && y == z => Some(x.clone()),
-        _ => None,
-    };
-    if let Some(winner) = matching_triple {
-        if winner == 0 {
-            return None;
+            self.send_chat(room_name, &announcement, 0);
         } else {
-            match winner {
-                1 => {
-                    return Some(game_state.player1.unwrap());
-                }
-                2 => {
-                    return Some(game_state.player2.unwrap());
+            self.send_board(room_name);
+        }
+    }
+
+    /// ask the opponent to let `player_id` take back their last move
+    fn request_takeback(&mut self, room_name: &str, player_id: usize) {
+        let (moves, bot_opponent) = match self.rooms.get_mut(room_name) {
+            Some(room) => {
+                let moves = if !room.game_state.seats().contains(&Some(player_id)) {
+                    Err(MoveError::NotSeated)
+                } else if room.game_state.outcome().is_over() {
+                    Err(MoveError::GameOver)
+                } else {
+                    room.moves_to_take_back(player_id)
+                        .ok_or(MoveError::NothingToTakeBack)
+                };
+                match moves {
+                    Ok(moves) => {
+                        room.pending_takeback = Some(TakebackRequest {
+                            requested_by: player_id,
+                            moves,
+                        });
+                        (moves, room.only_bots_against(player_id))
+                    }
+                    Err(e) => {
+                        self.send_error(player_id, e, &e.to_string());
+                        return;
+                    }
                 }
-                err => unreachable!("there should only be symbols 0, 1, and 2. found {}", err),
             }
+            None => return,
+        };
+        if bot_opponent {
+            // computer players never mind, but any human still at the table
+            // gets to answer for themselves
+            self.answer_takeback(room_name, None, true);
+            return;
+        }
+        let announcement = format!(
+            "player {} asks to take back {} move(s), send takeback_accept or takeback_decline",
+            player_id, moves
+        );
+        self.send_chat(room_name, &announcement, 0);
+    }
+
+    /// accept or decline the open takeback. `answered_by` is `None` when a
+    /// computer player answers, otherwise it has to be someone seated
+    fn answer_takeback(&mut self, room_name: &str, answered_by: Option<usize>, accept: bool) {
+        if let Some(id) = answered_by {
+            let seated = match self.rooms.get(room_name) {
+                Some(room) => room.game_state.seats().contains(&Some(id)),
+                None => return,
+            };
+            if !seated {
+                let e = MoveError::NotSeated;
+                self.send_error(id, e, &e.to_string());
+                return;
+            }
+        }
+        let request = match self.rooms.get_mut(room_name) {
+            Some(room) => match room.pending_takeback.take() {
+                Some(request) if Some(request.requested_by) != answered_by => {
+                    if accept {
+                        room.take_back(request.moves);
+                    }
+                    request
+                }
+                not_for_them => {
+                    room.pending_takeback = not_for_them;
+                    if let Some(id) = answered_by {
+                        let e = MoveError::NoRequestToAnswer;
+                        self.send_error(id, e, &e.to_string());
+                    }
+                    return;
+                }
+            },
+            None => return,
+        };
+        if accept {
+            let announcement = format!("player {} took back their move", request.requested_by);
+            self.send_chat(room_name, &announcement, 0);
+            self.send_board(room_name);
+            self.play_bot_turns(room_name);
+        } else {
+            self.send_chat(room_name, "the takeback was declined", 0);
         }
     }
-    None
-}
 
-/// returns the player who won, if someone won
-fn get_winner(game_state: &TicTacToeGame) -> Option<usize> {
-    let width = game_state.grid.len();
-    let height = game_state.grid[0].len();
-    // check each row
-    for row in 0..width {
-        let this_row = &game_state.grid[row];
-        // they all have to be the same
-        let winner = get_winner_of_triple(this_row.to_vec(), game_state);
-        if winner.is_some() {
-            return winner;
+    /// offer the opponent another game once this one is over
+    fn request_rematch(&mut self, room_name: &str, player_id: usize) {
+        let bot_opponent = match self.rooms.get_mut(room_name) {
+            Some(room) => {
+                let refused = if !room.game_state.seats().contains(&Some(player_id)) {
+                    Some(MoveError::NotSeated)
+                } else if !room.game_state.outcome().is_over() {
+                    Some(MoveError::GameInProgress)
+                } else {
+                    None
+                };
+                if let Some(e) = refused {
+                    self.send_error(player_id, e, &e.to_string());
+                    return;
+                }
+                room.pending_rematch = Some(player_id);
+                room.only_bots_against(player_id)
+            }
+            None => return,
+        };
+        if bot_opponent {
+            self.answer_rematch(room_name, None, true);
+            return;
         }
+        let announcement = format!(
+            "player {} wants a rematch, send rematch_accept or rematch_decline",
+            player_id
+        );
+        self.send_chat(room_name, &announcement, 0);
     }
 
-    // check each col
-    for x in 0..width {
-        let mut this_col = Vec::new();
-        for y in 0..height {
-            this_col.push(game_state.grid[y][x]);
+    /// accept or decline the open rematch. `answered_by` is `None` when a
+    /// computer player answers, otherwise it has to be someone seated
+    fn answer_rematch(&mut self, room_name: &str, answered_by: Option<usize>, accept: bool) {
+        if let Some(id) = answered_by {
+            let seated = match self.rooms.get(room_name) {
+                Some(room) => room.game_state.seats().contains(&Some(id)),
+                None => return,
+            };
+            if !seated {
+                let e = MoveError::NotSeated;
+                self.send_error(id, e, &e.to_string());
+                return;
+            }
         }
-        let winner = get_winner_of_triple(this_col, game_state);
-        if winner.is_some() {
-            return winner;
+        match self.rooms.get_mut(room_name) {
+            Some(room) => match room.pending_rematch.take() {
+                Some(requested_by) if Some(requested_by) != answered_by => {
+                    if accept {
+                        room.next_game();
+                        // a rematch after a match starts the whole series over
+                        if let Some(score) = room.match_score.as_mut() {
+                            *score = MatchScore::new(score.best_of);
+                        }
+                    }
+                }
+                not_for_them => {
+                    room.pending_rematch = not_for_them;
+                    if let Some(id) = answered_by {
+                        let e = MoveError::NoRequestToAnswer;
+                        self.send_error(id, e, &e.to_string());
+                    }
+                    return;
+                }
+            },
+            None => return,
+        }
+        if accept {
+            self.send_chat(
+                room_name,
+                "rematch! the next player along goes first this time",
+                0,
+            );
+            self.send_board(room_name);
+            self.play_bot_turns(room_name);
+        } else {
+            self.send_chat(room_name, "the rematch was declined", 0);
         }
     }
 
-    // check the diagonals
-    let nw_to_se = vec![
-        game_state.grid[0][0],
-        game_state.grid[1][1],
-        game_state.grid[2][2],
-    ];
+    /// sit a computer player in the room's empty seat. `message` picks the
+    /// difficulty, an empty one gets the perfect player
+    fn add_bot(&mut self, room_name: &str, message: &str, requested_by: usize) {
+        let difficulty = if message.trim().is_empty() {
+            ai::Difficulty::default()
+        } else {
+            match serde_json::from_str(message) {
+                Ok(ai::Difficulty::Mcts { playouts }) if playouts > ai::MAX_PLAYOUTS => {
+                    let detail = format!(
+                        "{}: at most {} playouts",
+                        MoveError::Malformed,
+                        ai::MAX_PLAYOUTS
+                    );
+                    self.send_error(requested_by, MoveError::Malformed, &detail);
+                    return;
+                }
+                Ok(difficulty) => difficulty,
+                Err(e) => {
+                    let detail = format!("{}: {}", MoveError::Malformed, e);
+                    self.send_error(requested_by, MoveError::Malformed, &detail);
+                    return;
+                }
+            }
+        };
+        let bot_id = self.rng.gen::<usize>();
+        let seated = match self.rooms.get_mut(room_name) {
+            Some(room) => {
+                room.game_state.add_player(bot_id);
+                let seated = room.game_state.seats().contains(&Some(bot_id));
+                if seated {
+                    room.bots.insert(bot_id, difficulty);
+                }
+                seated
+            }
+            None => false,
+        };
+        if !seated {
+            let e = MoveError::SeatsFull;
+            self.send_error(requested_by, e, &e.to_string());
+            return;
+        }
+        self.send_chat(room_name, "a computer player sat down", 0);
+        self.send_board(room_name);
+        self.play_bot_turns(room_name);
+    }
 
-    let winner = get_winner_of_triple(nw_to_se, game_state);
-    if winner.is_some() {
-        return winner;
+    /// if it is a computer player's turn, set it thinking. its move comes
+    /// back as a `BotMove`, with its clock running in the meantime
+    fn play_bot_turns(&mut self, room_name: &str) {
+        let (worker, replies) = match (&self.bot_worker, &self.bot_replies) {
+            (Some(worker), Some(replies)) => (worker.clone(), replies.clone()),
+            _ => return,
+        };
+        let search = self.searches + 1;
+        let think = match self.rooms.get_mut(room_name) {
+            Some(room) if room.thinking.is_none() => match room.game_state.current_player() {
+                Some(id) if room.bots.contains_key(&id) => {
+                    room.thinking = Some(search);
+                    Think {
+                        room: room_name.to_owned(),
+                        bot_id: id,
+                        search,
+                        game: room.game_state.clone(),
+                        difficulty: room.bots[&id],
+                        reply_to: replies,
+                    }
+                }
+                _ => return,
+            },
+            _ => return,
+        };
+        self.searches = search;
+        worker.do_send(think);
     }
 
-    let ne_to_sw = vec![
-        game_state.grid[2][0],
-        game_state.grid[1][1],
-        game_state.grid[0][2],
-    ];
-    let winner = get_winner_of_triple(ne_to_sw, game_state);
-    if winner.is_some() {
-        return winner;
+    /// play the move a computer player came back with, unless the game moved
+    /// on while it was thinking
+    fn bot_moved(&mut self, msg: BotMove) {
+        match self.rooms.get_mut(&msg.room) {
+            Some(room) if room.thinking == Some(msg.search) => room.thinking = None,
+            _ => return,
+        }
+        let turn = match msg.turn {
+            Some(turn) => turn,
+            None => return,
+        };
+        if let Err(e) = self.play_move(&msg.room, msg.bot_id, &turn) {
+            println!("the bot picked a move it can't play: {}", e);
+            return;
+        }
+        self.play_bot_turns(&msg.room);
     }
 
-    None
-}
+    /// tell one session why their request did nothing
+    fn send_error(&self, id: usize, error: MoveError, content: &str) {
+        if let Some(addr) = self.sessions.get(&id) {
+            let _ = addr.do_send(GameMessage::Error(ErrorMessage {
+                event_type: "error".to_owned(),
+                error,
+                content: content.to_owned(),
+            }));
+        }
+    }
 
-impl ChatServer {
-    fn send_turn(&mut self, room_name: &str, message: &str, skip_id: usize) {
-        println!("sending the turn now");
+    /// the room owner swaps the game for one at the given position
+    fn set_position(&mut self, room_name: &str, position: &str, requested_by: usize) {
+        let result = match self.rooms.get_mut(room_name) {
+            Some(room) if room.owner != Some(requested_by) => {
+                let error = MoveError::NotRoomOwner;
+                Err((error, error.to_string()))
+            }
+            Some(room) => room.set_position(position).map_err(|e| {
+                let error = MoveError::Malformed;
+                (error, format!("{}: {}", error, e))
+            }),
+            None => return,
+        };
+        match result {
+            Ok(()) => {
+                let announcement = format!("the position was set to {}", position.trim());
+                self.send_chat(room_name, &announcement, 0);
+                self.send_board(room_name);
+                self.play_bot_turns(room_name);
+            }
+            Err((error, detail)) => self.send_error(requested_by, error, &detail),
+        }
+    }
+
+    /// send the room's current game state to everyone in it
+    fn send_board(&mut self, room_name: &str) {
         if let Some(room) = self.rooms.get_mut(room_name) {
-            let gameturn: GameTurnMessage = serde_json::from_str(&message).unwrap();
-            let next_turn = advance_turn(skip_id, gameturn, room.game_state.clone());
-            let next_turn_json = serde_json::to_string(&next_turn).unwrap();
-            room.game_state = next_turn.clone();
+            if let Some(clock) = room.clock.as_mut() {
+                clock.charge(Instant::now());
+            }
             for id in &room.sessions_subscribed_to_room {
-                /* // the skip id is... not good?
-                if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(&id) {
-                        room.message_count += 1;
-                        let _ = addr.do_send(GameMessage::Turn(GameStateMessage {
-                            event_type: "board".to_owned(),
-                            content: next_turn.clone(),
-                        }));
-                    }
-                }
-                */
-                if let Some(addr) = self.sessions.get(&id) {
+                if let Some(addr) = self.sessions.get(id) {
                     room.message_count += 1;
-                    let _ = addr.do_send(GameMessage::Turn(GameStateMessage {
+                    let _ = addr.do_send(GameMessage::Turn(Box::new(GameStateMessage {
                         player_id: *id,
                         event_type: "board".to_owned(),
-                        content: next_turn.clone(),
-                    }));
+                        content: room.game_state.state(),
+                        match_score: room.match_score.clone(),
+                        clock: room.clock.clone(),
+                        position: room.game_state.position(),
+                    })));
                 }
             }
         }
@@ -348,6 +1055,21 @@ impl Actor for ChatServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(CLOCK_TICK, |act, _| act.tick_clocks());
+        self.bot_worker = Some(SyncArbiter::start(BOT_THREADS, || BotWorker));
+        self.bot_replies = Some(ctx.address().recipient());
+    }
+}
+
+/// Handler for `BotMove` message.
+impl Handler<BotMove> for ChatServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: BotMove, _: &mut Context<Self>) {
+        self.bot_moved(msg);
+    }
 }
 
 /// Handler for Connect message.
@@ -377,6 +1099,9 @@ impl Handler<Connect> for ChatServer {
 
         // make the joiner a player
         main_room.game_state.add_player(id);
+        main_room.sync_clock(Instant::now());
+        self.send_board("Main");
+        self.play_bot_turns("Main");
 
         // send id back
         id
@@ -394,6 +1119,7 @@ impl Handler<Disconnect> for ChatServer {
 
         // remove address
         if self.sessions.remove(&msg.id).is_some() {
+            let now = Instant::now();
             // remove session from all rooms
             for (name, room) in &mut self.rooms {
                 if room.sessions_subscribed_to_room.remove(&msg.id) {
@@ -401,6 +1127,7 @@ impl Handler<Disconnect> for ChatServer {
                 }
                 // also remove that player.
                 room.game_state.remove_player(msg.id);
+                room.sync_clock(now);
             }
         }
         // send message to other users
@@ -418,6 +1145,14 @@ impl Handler<ClientMessage> for ChatServer {
         match msg.event_type.as_ref() {
             "chatmessage" => self.send_chat(&msg.room, &msg.msg.to_owned(), msg.id),
             "move" => self.send_turn(&msg.room, &msg.msg.to_owned(), msg.id),
+            "add_bot" => self.add_bot(&msg.room, &msg.msg, msg.id),
+            "takeback" => self.request_takeback(&msg.room, msg.id),
+            "takeback_accept" => self.answer_takeback(&msg.room, Some(msg.id), true),
+            "takeback_decline" => self.answer_takeback(&msg.room, Some(msg.id), false),
+            "rematch" => self.request_rematch(&msg.room, msg.id),
+            "rematch_accept" => self.answer_rematch(&msg.room, Some(msg.id), true),
+            "rematch_decline" => self.answer_rematch(&msg.room, Some(msg.id), false),
+            "set_position" => self.set_position(&msg.room, &msg.msg, msg.id),
             e_type => {
                 println!("some kind of error???? {} ", e_type);
             }
@@ -440,35 +1175,74 @@ impl Handler<ListRooms> for ChatServer {
     }
 }
 
+/// Handler for `GetRecord` message.
+impl Handler<GetRecord> for ChatServer {
+    type Result = MessageResult<GetRecord>;
+
+    fn handle(&mut self, msg: GetRecord, _: &mut Context<Self>) -> Self::Result {
+        let room = match self.rooms.get(&msg.room) {
+            Some(room) => room,
+            None => return MessageResult(None),
+        };
+        let mut record = match room.game_state.record() {
+            Some(record) => record,
+            None => return MessageResult(None),
+        };
+        record.date = record::today();
+        for (name, seat) in record.players.iter_mut().zip(room.game_state.seats()) {
+            if seat.iter().any(|id| room.bots.contains_key(id)) {
+                *name = "computer".to_owned();
+            }
+        }
+        MessageResult(Some(record.to_string()))
+    }
+}
+
 /// Join room, send disconnect message to old room
 /// send join message to new room
 impl Handler<Join> for ChatServer {
-    type Result = ();
+    type Result = Result<(), String>;
+
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) -> Self::Result {
+        let Join { id, name, options } = msg;
+
+        // build the game before leaving anything, a bad board keeps you where you are
+        let new_room = if self.rooms.contains_key(&name) {
+            None
+        } else {
+            let mut room = ChatRoom::with_options(options)?;
+            room.owner = Some(id);
+            Some(room)
+        };
 
-    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
-        let Join { id, name } = msg;
         let mut rooms = Vec::new();
+        let now = Instant::now();
 
         // remove session from all rooms
         for (n, room) in &mut self.rooms {
             if room.sessions_subscribed_to_room.remove(&id) {
                 rooms.push(n.to_owned());
             }
+            room.game_state.remove_player(id);
+            room.sync_clock(now);
         }
         // send message to other users
         for room in rooms {
             self.send_chat(&room, "Someone disconnected", 0);
+            self.send_board(&room);
         }
 
-        if self.rooms.get_mut(&name).is_none() {
-            self.rooms.insert(name.clone(), ChatRoom::new());
+        if let Some(room) = new_room {
+            self.rooms.insert(name.clone(), room);
         }
         self.send_chat(&name, "Someone connected", id);
-        self.rooms
-            .get_mut(&name)
-            .unwrap()
-            .sessions_subscribed_to_room
-            .insert(id);
+        let room = self.rooms.get_mut(&name).unwrap();
+        room.sessions_subscribed_to_room.insert(id);
+        room.game_state.add_player(id);
+        room.sync_clock(now);
+        self.send_board(&name);
+        self.play_bot_turns(&name);
+        Ok(())
     }
 }
 
@@ -476,153 +1250,517 @@ impl Handler<Join> for ChatServer {
 mod tests {
     use super::*;
 
-    fn game(p1: usize, p2: usize, current: usize, grid: Vec<Vec<usize>>) -> TicTacToeGame {
-        let mut g = TicTacToeGame::new();
-        g.player1 = Some(p1);
-        g.player2 = Some(p2);
-        g.current_player_turn = Some(current);
-        g.grid = grid;
-        g
+    /// a game built from `json` room options
+    fn game_from(json: &str) -> Result<Box<dyn Game>, String> {
+        let options: GameOptions = serde_json::from_str(json).unwrap();
+        options.new_game()
     }
 
     #[test]
-    fn test_advance_a_turn1() {
-        let g = game(1, 2, 1, vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![0, 0],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![1, 0, 0], vec![0, 0, 0], vec![0, 0, 0]];
-        assert_eq!(grid, expected);
+    fn options_default_to_classic_tictactoe() {
+        let game = GameOptions::default().new_game().unwrap();
+        assert_eq!(game.state()["width"], 3);
+        assert!(game_from(r#"{"variant": "chess"}"#).is_err());
     }
 
     #[test]
-    fn test_advance_a_turn2() {
-        let g = game(1, 2, 1, vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![1, 1],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![0, 0, 0], vec![0, 1, 0], vec![0, 0, 0]];
-        assert_eq!(grid, expected);
+    fn tictactoe_options_take_rules_and_blocked_squares() {
+        let game = game_from(r#"{"rules": {"misere": true, "pie": true}}"#).unwrap();
+        assert_eq!(game.state()["rules"]["misere"], true);
+        assert_eq!(game.state()["rules"]["wild"], false);
+        let game =
+            game_from(r#"{"rules": {"torus": true}, "blocked": [[1, 1]], "random_blocked": 2}"#)
+                .unwrap();
+        assert_eq!(game.state()["rules"]["torus"], true);
+        assert_eq!(game.state()["grid"][1][1], 3);
+        assert_eq!(game.legal_moves().len(), 6);
+        assert!(game_from(r#"{"blocked": [[3, 3]]}"#).is_err());
+        assert!(game_from(r#"{"boards": 3}"#).is_err());
+        assert!(game_from(r#"{"gomoku_rules": {"swap2": true}}"#).is_err());
     }
 
     #[test]
-    fn test_advance_a_turn3() {
-        let g = game(1, 2, 1, vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![1, 2],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 1, 0]];
-        assert_eq!(grid, expected);
+    fn ultimate_options_build_nine_boards() {
+        let game = game_from(r#"{"variant": "ultimate"}"#).unwrap();
+        assert_eq!(game.state()["boards"].as_array().unwrap().len(), 9);
     }
 
     #[test]
-    fn win_top_row() {
-        let g = game(1, 2, 1, vec![vec![1, 1, 0], vec![2, 2, 0], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![2, 0],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![1, 1, 1], vec![2, 2, 0], vec![0, 0, 0]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
+    fn qubic_options_reject_tictactoe_rules() {
+        let game = game_from(r#"{"variant": "qubic"}"#).unwrap();
+        assert_eq!(game.legal_moves().len(), 64);
+        assert!(game_from(r#"{"variant": "qubic", "rules": {"misere": true}}"#).is_err());
     }
 
     #[test]
-    fn win_bottom_row() {
-        let g = game(1, 2, 1, vec![vec![0, 0, 0], vec![2, 2, 0], vec![1, 1, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![2, 2],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![0, 0, 0], vec![2, 2, 0], vec![1, 1, 1]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
+    fn connect_four_options_reject_blocked_squares() {
+        let game = game_from(r#"{"variant": "connect_four"}"#).unwrap();
+        assert_eq!(game.state()["width"], 7);
+        assert_eq!(game.state()["height"], 6);
+        assert_eq!(game.legal_moves()[6].position, vec![6]);
+        assert!(game_from(r#"{"variant": "connect_four", "random_blocked": 1}"#).is_err());
     }
 
     #[test]
-    fn win_left_col() {
-        let g = game(1, 2, 1, vec![vec![1, 2, 0], vec![1, 2, 0], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![0, 2],
-            },
-            g,
+    fn numerical_options_split_the_numbers() {
+        let game = game_from(r#"{"variant": "numerical"}"#).unwrap();
+        assert_eq!(
+            game.state()["numbers_left"][1],
+            serde_json::json!([2, 4, 6, 8])
         );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![1, 2, 0], vec![1, 2, 0], vec![1, 0, 0]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
     }
 
     #[test]
-    fn win_right_col() {
-        let g = game(1, 2, 1, vec![vec![0, 2, 1], vec![0, 2, 1], vec![0, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![2, 2],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![0, 2, 1], vec![0, 2, 1], vec![0, 0, 1]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
+    fn order_chaos_options_offer_both_marks() {
+        let game = game_from(r#"{"variant": "order_chaos"}"#).unwrap();
+        assert_eq!(game.legal_moves().len(), 72);
     }
 
     #[test]
-    fn win_ne_sw_diag() {
-        let g = game(1, 2, 1, vec![vec![1, 2, 0], vec![0, 0, 2], vec![0, 0, 1]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![1, 1],
-            },
-            g,
-        );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![1, 2, 0], vec![0, 1, 2], vec![0, 0, 1]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
+    fn notakto_options_take_a_board_count() {
+        let game = game_from(r#"{"variant": "notakto", "boards": 3}"#).unwrap();
+        assert_eq!(game.state()["boards"].as_array().unwrap().len(), 3);
     }
 
     #[test]
-    fn win_nw_se_diag() {
-        let g = game(1, 2, 1, vec![vec![0, 2, 1], vec![0, 0, 2], vec![1, 0, 0]]);
-        let turn = advance_turn(
-            1,
-            GameTurnMessage {
-                position: vec![1, 1],
-            },
-            g,
+    fn quantum_options_play_in_pairs_of_squares() {
+        let game = game_from(r#"{"variant": "quantum"}"#).unwrap();
+        assert_eq!(game.legal_moves()[0].position, vec![0, 0, 1, 0]);
+    }
+
+    #[test]
+    fn gomoku_options_reject_tictactoe_rules() {
+        let game = game_from(r#"{"variant": "gomoku", "gomoku_rules": {"renju": true}}"#).unwrap();
+        assert_eq!(game.legal_moves().len(), 225);
+        assert_eq!(game.state()["rules"]["renju"], true);
+        assert!(game_from(r#"{"variant": "gomoku", "rules": {"misere": true}}"#).is_err());
+    }
+
+    #[test]
+    fn fixed_size_variants_reject_size_options() {
+        for variant in &[
+            "ultimate",
+            "qubic",
+            "numerical",
+            "order_chaos",
+            "notakto",
+            "quantum",
+            "gomoku",
+        ] {
+            for size in &[r#""width": 4"#, r#""height": 4"#, r#""win_length": 4"#] {
+                let options = format!(r#"{{"variant": "{}", {}}}"#, variant, size);
+                assert!(game_from(&options).is_err(), "{}", options);
+            }
+        }
+        let game = game_from(r#"{"variant": "connect_four", "width": 9}"#).unwrap();
+        assert_eq!(game.state()["width"], 9);
+    }
+
+    #[test]
+    fn multiplayer_options_need_two_to_six_players() {
+        let game =
+            game_from(r#"{"players": 3, "width": 6, "height": 6, "win_length": 4}"#).unwrap();
+        assert_eq!(game.seat_count(), 3);
+        assert_eq!(game.legal_moves().len(), 36);
+        for options in &[
+            r#"{"players": 1}"#,
+            r#"{"players": 7}"#,
+            r#"{"players": 3, "position": "3/3/3 x 3x3 3"}"#,
+            r#"{"players": 3, "rules": {"torus": true}}"#,
+            r#"{"variant": "qubic", "players": 3}"#,
+        ] {
+            assert!(game_from(options).is_err(), "{}", options);
+        }
+    }
+
+    fn room_with_players() -> ChatRoom {
+        let mut room = ChatRoom::new();
+        room.game_state.add_player(1);
+        room.game_state.add_player(2);
+        room
+    }
+
+    fn play(room: &mut ChatRoom, player_id: usize, x: usize, y: usize) {
+        play_at(room, player_id, vec![x, y]);
+    }
+
+    fn play_at(room: &mut ChatRoom, player_id: usize, position: Vec<usize>) {
+        let before = room.game_state.clone();
+        let turn = GameTurnMessage { position };
+        room.game_state.apply_move(player_id, &turn).unwrap();
+        room.history.push((player_id, before));
+    }
+
+    #[test]
+    fn clock_runs_for_whoever_is_to_move() {
+        let options = GameOptions {
+            clock: Some(ClockOptions {
+                initial_seconds: 60,
+                increment_seconds: 0,
+            }),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        room.game_state.add_player(1);
+        room.game_state.add_player(2);
+        room.sync_clock(Instant::now());
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(1));
+        play(&mut room, 1, 0, 0);
+        room.sync_clock(Instant::now());
+        let clock = room.clock.as_ref().unwrap();
+        assert_eq!(clock.running, Some(2));
+        assert_eq!(clock.remaining_ms.len(), 2);
+
+        let no_time = GameOptions {
+            clock: Some(ClockOptions {
+                initial_seconds: 0,
+                increment_seconds: 5,
+            }),
+            ..GameOptions::default()
+        };
+        assert!(no_time.new_game().is_err());
+    }
+
+    #[test]
+    fn clocks_stop_while_a_seat_is_empty() {
+        let options = GameOptions {
+            clock: Some(ClockOptions {
+                initial_seconds: 1,
+                increment_seconds: 0,
+            }),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        room.game_state.add_player(1);
+        room.game_state.add_player(2);
+        let start = Instant::now();
+        room.sync_clock(start);
+        play(&mut room, 1, 0, 0);
+        // whoever is to move leaves, and so does the other side after
+        // coming back
+        for &leaver in &[2, 1] {
+            room.game_state.remove_player(leaver);
+            assert_eq!(room.game_state.current_player(), None);
+            room.sync_clock(start);
+            room.sync_clock(start + Duration::from_secs(5));
+            let clock = room.clock.as_ref().unwrap();
+            assert_eq!(clock.running, None);
+            assert_eq!(clock.flagged(), None);
+            assert_eq!(room.game_state.outcome(), GameOutcome::InProgress);
+            room.game_state.add_player(leaver);
+            room.sync_clock(start + Duration::from_secs(5));
+            assert_eq!(room.clock.as_ref().unwrap().running, Some(2));
+        }
+    }
+
+    #[test]
+    fn multiplayer_clocks_keep_running_past_an_empty_seat() {
+        let options = GameOptions {
+            players: Some(3),
+            clock: Some(ClockOptions {
+                initial_seconds: 60,
+                increment_seconds: 0,
+            }),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        for id in 1..=3 {
+            room.game_state.add_player(id);
+        }
+        play(&mut room, 1, 0, 0);
+        room.game_state.remove_player(2);
+        room.sync_clock(Instant::now());
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(3));
+
+        // a knocked out player's seat is never filled again, play goes on
+        room.game_state.forfeit(3);
+        room.game_state.add_player(2);
+        room.game_state.remove_player(3);
+        room.sync_clock(Instant::now());
+        assert_eq!(room.game_state.seats(), vec![Some(1), Some(2), None]);
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(1));
+        play(&mut room, 1, 1, 0);
+        room.sync_clock(Instant::now());
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(2));
+    }
+
+    #[test]
+    fn takebacks_hand_the_clock_back() {
+        let options = GameOptions {
+            clock: Some(ClockOptions {
+                initial_seconds: 60,
+                increment_seconds: 0,
+            }),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        room.game_state.add_player(1);
+        room.game_state.add_player(2);
+        play(&mut room, 1, 0, 0);
+        room.sync_clock(Instant::now());
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(2));
+        room.take_back(1);
+        assert_eq!(room.clock.as_ref().unwrap().running, Some(1));
+    }
+
+    #[test]
+    fn takeback_rolls_back_to_before_your_last_move() {
+        let mut room = room_with_players();
+        play(&mut room, 1, 0, 0);
+        play(&mut room, 2, 1, 1);
+        assert_eq!(room.moves_to_take_back(2), Some(1));
+        assert_eq!(room.moves_to_take_back(1), Some(2));
+
+        room.take_back(2);
+        assert_eq!(room.game_state.current_player(), Some(1));
+        assert_eq!(room.game_state.legal_moves().len(), 9);
+        assert_eq!(room.game_state.state()["moves"], serde_json::json!([]));
+        assert_eq!(room.moves_to_take_back(1), None);
+    }
+
+    #[test]
+    fn takebacks_go_by_who_moved_when_everyone_plays_x() {
+        let options = GameOptions {
+            variant: "notakto".to_owned(),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        room.game_state.add_player(1);
+        room.game_state.add_player(2);
+        play_at(&mut room, 1, vec![0, 0, 0]);
+        play_at(&mut room, 2, vec![0, 1, 0]);
+        assert_eq!(room.moves_to_take_back(2), Some(1));
+        assert_eq!(room.moves_to_take_back(1), Some(2));
+        room.take_back(1);
+        assert_eq!(room.game_state.current_player(), Some(2));
+        assert_eq!(room.game_state.legal_moves().len(), 8);
+    }
+
+    fn server_with(room: ChatRoom) -> ChatServer {
+        let mut server = ChatServer::default();
+        server.rooms.insert("test".to_owned(), room);
+        server
+    }
+
+    #[test]
+    fn only_seated_players_ask_for_and_answer_takebacks() {
+        let mut room = room_with_players();
+        play(&mut room, 1, 0, 0);
+        let mut server = server_with(room);
+        server.request_takeback("test", 3);
+        assert!(server.rooms["test"].pending_takeback.is_none());
+        server.request_takeback("test", 1);
+        server.answer_takeback("test", Some(3), true);
+        assert!(server.rooms["test"].pending_takeback.is_some());
+        server.answer_takeback("test", Some(2), true);
+        assert!(server.rooms["test"].history.is_empty());
+
+        // nothing to take back once the game is over
+        let mut room = room_with_players();
+        for &(id, x, y) in &[(1, 0, 0), (2, 1, 1), (1, 1, 0), (2, 2, 2), (1, 2, 0)] {
+            play(&mut room, id, x, y);
+        }
+        let mut server = server_with(room);
+        server.request_takeback("test", 2);
+        assert!(server.rooms["test"].pending_takeback.is_none());
+    }
+
+    #[test]
+    fn bots_only_answer_takebacks_when_no_other_human_is_seated() {
+        let options = GameOptions {
+            players: Some(3),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        for id in 1..=3 {
+            room.game_state.add_player(id);
+        }
+        room.bots.insert(3, ai::Difficulty::Random);
+        play(&mut room, 1, 0, 0);
+        let mut server = server_with(room);
+        server.request_takeback("test", 1);
+        assert!(server.rooms["test"].pending_takeback.is_some());
+        assert_eq!(server.rooms["test"].history.len(), 1);
+
+        server
+            .rooms
+            .get_mut("test")
+            .unwrap()
+            .bots
+            .insert(2, ai::Difficulty::Random);
+        server.request_takeback("test", 1);
+        assert!(server.rooms["test"].pending_takeback.is_none());
+        assert!(server.rooms["test"].history.is_empty());
+    }
+
+    #[test]
+    fn only_the_seated_opponent_answers_a_rematch() {
+        let mut room = room_with_players();
+        for &(id, x, y) in &[(1, 0, 0), (2, 1, 1), (1, 1, 0), (2, 2, 2), (1, 2, 0)] {
+            play(&mut room, id, x, y);
+        }
+        let mut server = server_with(room);
+        server.request_rematch("test", 1);
+        assert_eq!(server.rooms["test"].pending_rematch, Some(1));
+        // a spectator's accept and the asker's own are both refused
+        for &answerer in &[3, 1] {
+            server.answer_rematch("test", Some(answerer), true);
+            assert_eq!(server.rooms["test"].pending_rematch, Some(1));
+            assert!(server.rooms["test"].game_state.outcome().is_over());
+        }
+        server.answer_rematch("test", Some(2), true);
+        assert_eq!(server.rooms["test"].pending_rematch, None);
+        assert_eq!(
+            server.rooms["test"].game_state.outcome(),
+            GameOutcome::InProgress
         );
-        let grid = turn.grid.clone();
-        let expected = vec![vec![0, 2, 1], vec![0, 1, 2], vec![1, 0, 0]];
-        assert_eq!(grid, expected);
-        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn bots_are_refused_too_many_playouts() {
+        let mut room = ChatRoom::new();
+        room.game_state.add_player(1);
+        let mut server = server_with(room);
+        server.add_bot("test", r#"{"difficulty": "mcts", "playouts": 10001}"#, 1);
+        assert!(server.rooms["test"].bots.is_empty());
+        server.add_bot("test", r#"{"difficulty": "mcts", "playouts": 10000}"#, 1);
+        assert_eq!(server.rooms["test"].bots.len(), 1);
+    }
+
+    #[test]
+    fn bot_moves_for_a_position_that_moved_on_are_dropped() {
+        let mut room = room_with_players();
+        room.bots.insert(2, ai::Difficulty::Random);
+        play(&mut room, 1, 0, 0);
+        room.thinking = Some(1);
+        let mut server = server_with(room);
+        let answer = |search, x| BotMove {
+            room: "test".to_owned(),
+            bot_id: 2,
+            search,
+            turn: Some(GameTurnMessage {
+                position: vec![x, 1],
+            }),
+        };
+        server.bot_moved(answer(2, 1));
+        assert_eq!(server.rooms["test"].history.len(), 1);
+        server.bot_moved(answer(1, 1));
+        assert_eq!(server.rooms["test"].history.len(), 2);
+        assert_eq!(server.rooms["test"].thinking, None);
+
+        // taking back while the bot thinks throws its answer away
+        let room = server.rooms.get_mut("test").unwrap();
+        room.thinking = Some(3);
+        room.take_back(1);
+        assert_eq!(room.thinking, None);
+        server.bot_moved(answer(3, 2));
+        assert_eq!(server.rooms["test"].history.len(), 1);
+    }
+
+    #[test]
+    fn three_player_rooms_take_back_every_reply_and_rotate_who_starts() {
+        let options = GameOptions {
+            players: Some(3),
+            ..GameOptions::default()
+        };
+        let mut room = ChatRoom::with_options(options).unwrap();
+        for id in 1..=3 {
+            room.game_state.add_player(id);
+        }
+        for (x, id) in (1..=3).enumerate() {
+            play_at(&mut room, id, vec![x, 0]);
+        }
+        assert_eq!(room.moves_to_take_back(1), Some(3));
+        room.take_back(3);
+        assert_eq!(room.game_state.current_player(), Some(1));
+        room.next_game();
+        assert_eq!(room.game_state.seats(), vec![Some(2), Some(3), Some(1)]);
+        assert_eq!(room.game_state.current_player(), Some(2));
+    }
+
+    #[test]
+    fn next_game_keeps_players_and_swaps_who_starts() {
+        let mut room = room_with_players();
+        play(&mut room, 1, 0, 0);
+        room.next_game();
+        assert_eq!(room.game_state.seats(), vec![Some(2), Some(1)]);
+        assert_eq!(room.game_state.current_player(), Some(2));
+        assert_eq!(room.game_state.legal_moves().len(), 9);
+        assert!(room.history.is_empty());
+        room.next_game();
+        assert_eq!(room.game_state.current_player(), Some(1));
+    }
+
+    #[test]
+    fn set_position_keeps_everyone_seated() {
+        let mut room = room_with_players();
+        play(&mut room, 1, 0, 0);
+        room.set_position("x2/1o1/3 x 3x3 3").unwrap();
+        assert_eq!(room.game_state.seats(), vec![Some(1), Some(2)]);
+        assert_eq!(room.game_state.current_player(), Some(1));
+        assert_eq!(room.game_state.legal_moves().len(), 7);
+        assert!(room.history.is_empty());
+        assert!(room.set_position("nonsense").is_err());
+        assert_eq!(room.game_state.position().unwrap(), "x2/1o1/3 x 3x3 3");
+    }
+
+    #[test]
+    fn rooms_can_start_from_a_shared_position() {
+        let options = GameOptions {
+            position: Some("4/4/xo2/4 x 4x4 3".to_owned()),
+            ..GameOptions::default()
+        };
+        let game = options.new_game().unwrap();
+        assert_eq!(game.state()["width"], 4);
+        assert_eq!(game.legal_moves().len(), 14);
+    }
+
+    fn won_by(player: usize) -> GameOutcome {
+        GameOutcome::Won {
+            player,
+            cells: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn match_goes_to_whoever_wins_a_majority() {
+        let mut score = MatchScore::new(5);
+        score.record(&won_by(1));
+        score.record(&GameOutcome::Draw);
+        score.record(&won_by(2));
+        score.record(&won_by(1));
+        assert!(!score.finished);
+        score.record(&won_by(1));
+        assert!(score.finished);
+        assert_eq!(score.winner, Some(1));
+        assert_eq!(score.games_played, 5);
+        assert_eq!(score.draws, 1);
+    }
+
+    #[test]
+    fn matches_are_one_to_99_games() {
+        for &(best_of, ok) in &[(0, false), (1, true), (99, true), (100, false)] {
+            let options = GameOptions {
+                best_of: Some(best_of),
+                ..GameOptions::default()
+            };
+            assert_eq!(options.new_game().is_ok(), ok, "best of {}", best_of);
+        }
+    }
+
+    #[test]
+    fn match_without_a_majority_can_be_drawn() {
+        let mut score = MatchScore::new(3);
+        score.record(&won_by(1));
+        score.record(&GameOutcome::Draw);
+        score.record(&won_by(2));
+        assert!(score.finished);
+        assert_eq!(score.winner, None);
+
+        let mut score = MatchScore::new(3);
+        score.record(&GameOutcome::Draw);
+        score.record(&GameOutcome::Draw);
+        score.record(&won_by(2));
+        assert_eq!(score.winner, Some(2));
     }
 }
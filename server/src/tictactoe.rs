@@ -0,0 +1,1032 @@
+//! Tic-tac-toe on any board up to `MAX_BOARD_SIZE` a side, won by getting
+//! `win_length` in a row.
+
+use bitboard::{check_size, torus_win_masks, win_masks, Bitboard, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use record::GameRecord;
+use serde::{Deserialize, Serialize};
+use std::rc::Rc;
+
+/// the largest board side we will build a game for
+pub const MAX_BOARD_SIZE: usize = 16;
+
+/// twists on the classic rules, picked when the room is made. everything is
+/// off by default
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// completing a line loses instead of winning
+    pub misere: bool,
+    /// every move may place either mark, and whoever completes a line of
+    /// either one has made it
+    pub wild: bool,
+    /// the second player may answer the first move by taking it over and
+    /// swapping sides
+    pub pie: bool,
+    /// the edges wrap around, so lines may run off one side of the board and
+    /// carry on from the other
+    pub torus: bool,
+}
+
+/// what a move that passed `check_move` does
+enum Play {
+    /// put `mark` on (x, y)
+    Place { mark: Seat, x: usize, y: usize },
+    /// trade sides under the pie rule
+    Swap,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TicTacToeGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    pub width: usize,
+    pub height: usize,
+    /// how many in a row it takes to win
+    pub win_length: usize,
+    /// the marks, sent to clients as rows of cells
+    pub grid: Board,
+    pub winner: Option<usize>,
+    #[serde(default)]
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    #[serde(default)]
+    pub moves: Vec<PlayedMove>,
+    #[serde(default)]
+    pub rules: Rules,
+    /// the position the game was set up from, when it didn't start from an
+    /// empty board
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<String>,
+}
+
+impl Default for TicTacToeGame {
+    fn default() -> Self {
+        TicTacToeGame::with_size(3, 3, 3).expect("the classic board is always valid")
+    }
+}
+
+impl TicTacToeGame {
+    // rooms build their games from `GameOptions`, this is for the tests
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        TicTacToeGame::default()
+    }
+
+    /// a `width` by `height` board where `win_length` in a row wins
+    pub fn with_size(width: usize, height: usize, win_length: usize) -> Result<Self, String> {
+        check_size(width, height, win_length)?;
+        Ok(TicTacToeGame {
+            seating: Seating::default(),
+            width,
+            height,
+            win_length,
+            grid: Board::new(width, height),
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+            rules: Rules::default(),
+            setup: None,
+        })
+    }
+
+    pub fn add_player(&mut self, id: usize) {
+        // make the joiner a player, then set someone to have a turn
+        if !self.seating.add_player(id) {
+            println!("one of the players was not set, nobody's turn first");
+        } else if self.outcome.is_over() {
+            println!("the game is already over, nobody's turn");
+        } else if is_finished(self) {
+            self.outcome = get_outcome(self);
+            self.winner = self.outcome.winner();
+            println!("the game is already over, nobody's turn");
+        } else {
+            self.seating.start_turn();
+        }
+    }
+
+    /// take the empty square (x, y) out of play, for setting up a board
+    /// before the game starts
+    pub fn block(&mut self, x: usize, y: usize) -> Result<(), String> {
+        if x >= self.width || y >= self.height {
+            return Err(format!(
+                "can't block ({}, {}), it's off the {}x{} board",
+                x, y, self.width, self.height
+            ));
+        }
+        if self.grid.get(x, y) != Cell::Empty {
+            return Err(format!("can't block ({}, {}), it isn't empty", x, y));
+        }
+        self.grid.set(x, y, Cell::Blocked);
+        self.refresh_setup();
+        Ok(())
+    }
+
+    /// block `count` of the empty squares, picked at random
+    pub fn block_random<R: Rng>(&mut self, count: usize, rng: &mut R) -> Result<(), String> {
+        let empty = self.grid.empty().squares();
+        if count > empty.len() {
+            return Err(format!(
+                "can't block {} squares, only {} are empty",
+                count,
+                empty.len()
+            ));
+        }
+        for &(x, y) in empty.choose_multiple(rng, count) {
+            self.grid.set(x, y, Cell::Blocked);
+        }
+        self.refresh_setup();
+        Ok(())
+    }
+
+    /// keep a set up board's starting position in step with squares blocked
+    /// after it was read
+    fn refresh_setup(&mut self) {
+        if self.setup.is_some() {
+            self.setup = Some(self.position());
+        }
+    }
+
+    /// the board as one line, like `xx1/oo1/3 x 3x3 3`. rows go from the
+    /// top with `#` for blocked cells and runs of empty cells as a number,
+    /// then who moves next (`-` once the game is over), the board size and
+    /// the win length
+    pub fn position(&self) -> String {
+        let rows: Vec<String> = self
+            .grid
+            .rows()
+            .iter()
+            .map(|row| {
+                let mut text = String::new();
+                let mut empty = 0;
+                for &cell in row {
+                    let symbol = match cell {
+                        Cell::Empty => {
+                            empty += 1;
+                            continue;
+                        }
+                        Cell::Taken(seat) => seat.symbol(),
+                        Cell::Blocked => '#',
+                    };
+                    if empty > 0 {
+                        text.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    text.push(symbol);
+                }
+                if empty > 0 {
+                    text.push_str(&empty.to_string());
+                }
+                text
+            })
+            .collect();
+        let side = if is_finished(self) {
+            '-'
+        } else {
+            self.seating.to_move.symbol()
+        };
+        format!(
+            "{} {} {}x{} {}",
+            rows.join("/"),
+            side,
+            self.width,
+            self.height,
+            self.win_length
+        )
+    }
+
+    /// an empty-seated game at the position `position()` wrote, without a
+    /// move list since the position doesn't say how it was reached
+    pub fn from_position(position: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = position.split_whitespace().collect();
+        let (cells, side, size, win_length) = match fields.as_slice() {
+            [cells, side, size, win_length] => (*cells, *side, *size, *win_length),
+            _ => {
+                return Err(format!(
+                    "a position needs cells, side to move, size and win length, got {:?}",
+                    position
+                ))
+            }
+        };
+        let (width, height) = match size.find('x') {
+            Some(split) => (
+                size[..split]
+                    .parse()
+                    .map_err(|_| format!("bad size {:?}", size))?,
+                size[split + 1..]
+                    .parse()
+                    .map_err(|_| format!("bad size {:?}", size))?,
+            ),
+            None => return Err(format!("bad size {:?}", size)),
+        };
+        let win_length = win_length
+            .parse()
+            .map_err(|_| format!("bad win length {:?}", win_length))?;
+        let mut game = TicTacToeGame::with_size(width, height, win_length)?;
+
+        let rows: Vec<&str> = cells.split('/').collect();
+        if rows.len() != height {
+            return Err(format!("expected {} rows, got {}", height, rows.len()));
+        }
+        for (y, row) in rows.iter().enumerate() {
+            let mut x: usize = 0;
+            let mut empty = String::new();
+            for c in row.chars().chain(Some(' ')) {
+                if c.is_ascii_digit() {
+                    empty.push(c);
+                    continue;
+                }
+                if !empty.is_empty() {
+                    let run = empty.parse::<usize>().map_err(|e| e.to_string())?;
+                    x = match x.checked_add(run) {
+                        Some(end) if end <= width => end,
+                        _ => return Err(format!("row {} is longer than {}", y + 1, width)),
+                    };
+                    empty.clear();
+                }
+                let cell = match c {
+                    'x' => Cell::Taken(Seat::One),
+                    'o' => Cell::Taken(Seat::Two),
+                    '#' => Cell::Blocked,
+                    ' ' => break,
+                    other => return Err(format!("unknown cell {:?} in row {}", other, y + 1)),
+                };
+                if x >= width {
+                    return Err(format!("row {} is longer than {}", y + 1, width));
+                }
+                game.grid.set(x, y, cell);
+                x += 1;
+            }
+            if x != width {
+                return Err(format!("row {} has {} cells, not {}", y + 1, x, width));
+            }
+        }
+
+        // only a draw can be told apart before anyone sits down, a win is
+        // picked up by `add_player` once both seats are filled
+        game.outcome = get_outcome(&game);
+        game.seating.to_move = match (side, is_finished(&game)) {
+            ("x", false) => Seat::One,
+            ("o", false) => Seat::Two,
+            ("-", true) => Seat::One,
+            ("-", false) => return Err("the game is not over, someone has to move".to_owned()),
+            (_, true) => return Err("the game is over, nobody can move".to_owned()),
+            (other, _) => return Err(format!("unknown side to move {:?}", other)),
+        };
+        game.setup = Some(game.position());
+        Ok(game)
+    }
+}
+
+impl Game for TicTacToeGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        TicTacToeGame::add_player(self, id)
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, play) = check_move(player_id, turn, self)?;
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        match play {
+            Play::Place { mark, x, y } => {
+                self.grid.set(x, y, Cell::Taken(mark));
+                // advance the 'current player' state
+                self.seating.pass_turn(seat);
+            }
+            Play::Swap => self.seating.swap_seats(),
+        }
+        self.outcome = get_outcome(self);
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for (x, y) in self.grid.empty().squares() {
+            if self.rules.wild {
+                for mark in 1..=2 {
+                    moves.push(GameTurnMessage {
+                        position: vec![x, y, mark],
+                    });
+                }
+            } else {
+                moves.push(GameTurnMessage {
+                    position: vec![x, y],
+                });
+            }
+        }
+        if can_swap(self) {
+            moves.push(GameTurnMessage {
+                position: Vec::new(),
+            });
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+
+    fn record(&self) -> Option<GameRecord> {
+        Some(GameRecord::from_game(self))
+    }
+
+    fn position(&self) -> Option<String> {
+        Some(TicTacToeGame::position(self))
+    }
+}
+
+/// the seat of `player_id` and what `cm` does, if they may play it. a move
+/// is `[x, y]`, `[x, y, mark]` with the seat number of the mark in wild
+/// games, or `[]` to swap sides under the pie rule
+fn check_move(
+    player_id: usize,
+    cm: &GameTurnMessage,
+    game_state: &TicTacToeGame,
+) -> Result<(Seat, Play), MoveError> {
+    if game_state.outcome.is_over() {
+        return Err(MoveError::GameOver);
+    }
+    let seat = game_state.seating.check_turn(player_id)?;
+    let (x, y, mark) = match cm.position.as_slice() {
+        [] if can_swap(game_state) => return Ok((seat, Play::Swap)),
+        [] => return Err(MoveError::CantSwap),
+        [x, y] => (*x, *y, seat),
+        [x, y, mark] if game_state.rules.wild => match Seat::from_number(*mark as u64) {
+            Some(mark) => (*x, *y, mark),
+            None => return Err(MoveError::Malformed),
+        },
+        _ => return Err(MoveError::Malformed),
+    };
+    if x >= game_state.width || y >= game_state.height {
+        return Err(MoveError::OutOfBounds);
+    }
+    if game_state.grid.get(x, y) != Cell::Empty {
+        return Err(MoveError::Occupied);
+    }
+    Ok((seat, Play::Place { mark, x, y }))
+}
+
+/// whether the side to move may swap instead, only ever as the reply to the
+/// very first move
+fn can_swap(game_state: &TicTacToeGame) -> bool {
+    game_state.rules.pie && game_state.moves.len() == 1 && !game_state.outcome.is_over()
+}
+
+/// every line that wins, which wrap around the edges on a torus
+fn lines(game_state: &TicTacToeGame) -> Rc<Vec<Bitboard>> {
+    let (width, height, win_length) = (game_state.width, game_state.height, game_state.win_length);
+    if game_state.rules.torus {
+        torus_win_masks(width, height, win_length)
+    } else {
+        win_masks(width, height, win_length)
+    }
+}
+
+/// whether the board alone says the game is over, whoever is seated
+fn is_finished(game_state: &TicTacToeGame) -> bool {
+    game_state.grid.completed_line(&lines(game_state)).is_some() || game_state.grid.is_full()
+}
+
+/// won if any line is filled by one player, drawn once the board is full.
+/// the rules decide who a filled line counts for
+fn get_outcome(game_state: &TicTacToeGame) -> GameOutcome {
+    let masks = lines(game_state);
+    let rules = game_state.rules;
+    let winner = game_state
+        .grid
+        .completed_line(&masks)
+        .and_then(|(mark, line)| {
+            // in wild games the line belongs to whoever just moved, whatever
+            // mark it is made of
+            let maker = if rules.wild {
+                game_state.seating.to_move.next()
+            } else {
+                mark
+            };
+            let seat = if rules.misere { maker.next() } else { maker };
+            Some((game_state.seating.player_in(seat)?, line))
+        });
+    if let Some((player, line)) = winner {
+        return GameOutcome::Won {
+            player,
+            cells: line
+                .squares()
+                .into_iter()
+                .map(|(x, y)| vec![x, y])
+                .collect(),
+        };
+    }
+    if game_state.grid.is_full() {
+        GameOutcome::Draw
+    } else {
+        GameOutcome::InProgress
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// play `cm` for `player_id` on a copy of the game, or say why they can't
+    fn advance_turn(
+        player_id: usize,
+        cm: GameTurnMessage,
+        mut game_state: TicTacToeGame,
+    ) -> Result<TicTacToeGame, MoveError> {
+        game_state.apply_move(player_id, &cm)?;
+        Ok(game_state)
+    }
+
+    fn game(p1: usize, p2: usize, position: &str) -> TicTacToeGame {
+        let mut g = TicTacToeGame::from_position(position).unwrap();
+        g.add_player(p1);
+        g.add_player(p2);
+        g
+    }
+
+    /// a grid from the numbers clients see, 0 empty and 1 or 2 for a seat
+    fn cells(rows: Vec<Vec<usize>>) -> Board {
+        serde_json::from_value(serde_json::to_value(rows).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_advance_a_turn1() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![0, 0],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![1, 0, 0], vec![0, 0, 0], vec![0, 0, 0]]);
+        assert_eq!(grid, expected);
+    }
+
+    #[test]
+    fn test_advance_a_turn2() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![1, 1],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![0, 0, 0], vec![0, 1, 0], vec![0, 0, 0]]);
+        assert_eq!(grid, expected);
+    }
+
+    #[test]
+    fn test_advance_a_turn3() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![1, 2],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![0, 0, 0], vec![0, 0, 0], vec![0, 1, 0]]);
+        assert_eq!(grid, expected);
+    }
+
+    #[test]
+    fn win_top_row() {
+        let g = game(1, 2, "xx1/oo1/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![2, 0],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![1, 1, 1], vec![2, 2, 0], vec![0, 0, 0]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_bottom_row() {
+        let g = game(1, 2, "3/oo1/xx1 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![2, 2],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![0, 0, 0], vec![2, 2, 0], vec![1, 1, 1]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_left_col() {
+        let g = game(1, 2, "xo1/xo1/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![0, 2],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![1, 2, 0], vec![1, 2, 0], vec![1, 0, 0]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_right_col() {
+        let g = game(1, 2, "1ox/1ox/3 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![2, 2],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![0, 2, 1], vec![0, 2, 1], vec![0, 0, 1]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_ne_sw_diag() {
+        let g = game(1, 2, "xo1/2o/2x x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![1, 1],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![1, 2, 0], vec![0, 1, 2], vec![0, 0, 1]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_nw_se_diag() {
+        let g = game(1, 2, "1ox/2o/x2 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![1, 1],
+            },
+            g,
+        )
+        .unwrap();
+        let grid = turn.grid.clone();
+        let expected = cells(vec![vec![0, 2, 1], vec![0, 1, 2], vec![1, 0, 0]]);
+        assert_eq!(grid, expected);
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    fn sized_game(width: usize, height: usize, win_length: usize) -> TicTacToeGame {
+        let mut g = TicTacToeGame::with_size(width, height, win_length).unwrap();
+        g.seating.player1 = Some(1);
+        g.seating.player2 = Some(2);
+        g.seating.current_player_turn = Some(1);
+        g
+    }
+
+    #[test]
+    fn new_game_has_board_dimensions() {
+        let g = TicTacToeGame::with_size(7, 5, 4).unwrap();
+        assert_eq!(g.grid.height(), 5);
+        assert_eq!(g.grid.width(), 7);
+        let json = serde_json::to_value(&g).unwrap();
+        assert_eq!(json["width"], 7);
+        assert_eq!(json["height"], 5);
+        assert_eq!(json["win_length"], 4);
+    }
+
+    #[test]
+    fn rejects_boards_that_cannot_be_won() {
+        assert!(TicTacToeGame::with_size(3, 3, 4).is_err());
+        assert!(TicTacToeGame::with_size(0, 3, 3).is_err());
+        assert!(TicTacToeGame::with_size(MAX_BOARD_SIZE + 1, 3, 3).is_err());
+        assert!(TicTacToeGame::with_size(5, 3, 5).is_ok());
+    }
+
+    #[test]
+    fn win_five_by_five_diag_of_four() {
+        let mut g = sized_game(5, 5, 4);
+        for i in 1..4 {
+            g.grid.set(i, i, Cell::Taken(Seat::One));
+        }
+        assert_eq!(get_outcome(&g), GameOutcome::InProgress);
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![4, 4],
+            },
+            g,
+        )
+        .unwrap();
+        assert_eq!(turn.winner, Some(1));
+    }
+
+    #[test]
+    fn win_gomoku_ne_sw_diag() {
+        let mut g = sized_game(15, 15, 5);
+        for i in 0..4 {
+            g.grid.set(3 + i, 10 - i, Cell::Taken(Seat::Two));
+        }
+        g.seating.current_player_turn = Some(2);
+        let turn = advance_turn(
+            2,
+            GameTurnMessage {
+                position: vec![7, 6],
+            },
+            g,
+        )
+        .unwrap();
+        assert_eq!(turn.winner, Some(2));
+    }
+
+    #[test]
+    fn three_in_a_row_is_not_enough_on_a_seven_by_seven() {
+        let mut g = sized_game(7, 7, 4);
+        g.grid.set(4, 6, Cell::Taken(Seat::One));
+        g.grid.set(5, 6, Cell::Taken(Seat::One));
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![6, 6],
+            },
+            g,
+        )
+        .unwrap();
+        assert_eq!(turn.winner, None);
+    }
+
+    #[test]
+    fn full_board_without_a_line_is_a_draw() {
+        let g = game(1, 2, "xox/xoo/ox1 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![2, 2],
+            },
+            g,
+        )
+        .unwrap();
+        assert_eq!(turn.outcome, GameOutcome::Draw);
+        assert_eq!(turn.winner, None);
+        assert_eq!(turn.seating.current_player_turn, None);
+    }
+
+    #[test]
+    fn won_outcome_has_the_winning_cells() {
+        let g = game(1, 2, "xx1/o2/ox1 x 3x3 3");
+        let turn = advance_turn(
+            1,
+            GameTurnMessage {
+                position: vec![2, 0],
+            },
+            g,
+        )
+        .unwrap();
+        assert_eq!(
+            turn.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![0, 0], vec![1, 0], vec![2, 0]],
+            }
+        );
+        assert_eq!(turn.seating.current_player_turn, None);
+    }
+
+    #[test]
+    fn outcome_is_tagged_by_status_in_json() {
+        let json = serde_json::to_value(&GameOutcome::Draw).unwrap();
+        assert_eq!(json["status"], "draw");
+        let json = serde_json::to_value(TicTacToeGame::new()).unwrap();
+        assert_eq!(json["outcome"]["status"], "in_progress");
+    }
+
+    #[test]
+    fn games_keep_their_json_shape() {
+        let g = game(1, 2, "xo1/3/3 x 3x3 3");
+        let mut json = serde_json::to_value(&g).unwrap();
+        assert_eq!(json["grid"][0], serde_json::json!([1, 2, 0]));
+        assert_eq!(json["to_move"], 1);
+        let back: TicTacToeGame = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(back.grid, g.grid);
+
+        json["grid"][0][2] = serde_json::json!(4);
+        assert!(serde_json::from_value::<TicTacToeGame>(json).is_err());
+    }
+
+    #[test]
+    fn positions_round_trip() {
+        for position in &[
+            "3/3/3 x 3x3 3",
+            "xx1/oo1/3 x 3x3 3",
+            "x2/1o1/x2 o 3x3 3",
+            "xox/oxx/oxo - 3x3 3",
+            "x6/7/3o3/7/7 o 7x5 4",
+            "x#1/1o1/2# x 3x3 3",
+        ] {
+            let g = TicTacToeGame::from_position(position).unwrap();
+            assert_eq!(g.position(), *position);
+        }
+        let mut g = game(1, 2, "xx1/oo1/3 x 3x3 3");
+        g = play(g, 1, vec![2, 0]).unwrap();
+        assert_eq!(g.position(), "xxx/oo1/3 - 3x3 3");
+    }
+
+    #[test]
+    fn side_to_move_comes_from_the_position() {
+        let g = game(7, 8, "x2/3/3 o 3x3 3");
+        assert_eq!(g.seating.current_player_turn, Some(8));
+        let g = game(7, 8, "xxx/oo1/3 - 3x3 3");
+        assert_eq!(g.outcome.winner(), Some(7));
+        assert_eq!(g.seating.current_player_turn, None);
+    }
+
+    #[test]
+    fn bad_positions_are_refused() {
+        for position in &[
+            "",
+            "3/3/3 x 3x3",
+            "3/3 x 3x3 3",
+            "4/3/3 x 3x3 3",
+            "2/3/3 x 3x3 3",
+            "3/3/2z x 3x3 3",
+            "3/3/3 y 3x3 3",
+            "3/3/3 - 3x3 3",
+            "xxx/oo1/3 o 3x3 3",
+            "3/3/3 x 3by3 3",
+            "x18446744073709551615/3/3 x 3x3 3",
+            "x99999999999999999999/3/3 x 3x3 3",
+        ] {
+            assert!(
+                TicTacToeGame::from_position(position).is_err(),
+                "{}",
+                position
+            );
+        }
+    }
+
+    fn play(
+        g: TicTacToeGame,
+        player_id: usize,
+        position: Vec<usize>,
+    ) -> Result<TicTacToeGame, MoveError> {
+        advance_turn(player_id, GameTurnMessage { position }, g)
+    }
+
+    #[test]
+    fn cannot_play_on_a_taken_cell() {
+        let g = game(1, 2, "x2/3/3 o 3x3 3");
+        assert_eq!(play(g, 2, vec![0, 0]).unwrap_err(), MoveError::Occupied);
+    }
+
+    #[test]
+    fn cannot_play_off_the_board() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        assert_eq!(
+            play(g.clone(), 1, vec![3, 0]).unwrap_err(),
+            MoveError::OutOfBounds
+        );
+        assert_eq!(
+            play(g.clone(), 1, vec![0, 7]).unwrap_err(),
+            MoveError::OutOfBounds
+        );
+        assert_eq!(play(g, 1, vec![0]).unwrap_err(), MoveError::Malformed);
+    }
+
+    #[test]
+    fn cannot_play_out_of_turn() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        assert_eq!(
+            play(g.clone(), 2, vec![0, 0]).unwrap_err(),
+            MoveError::NotYourTurn
+        );
+        assert_eq!(play(g, 99, vec![0, 0]).unwrap_err(), MoveError::NotYourTurn);
+    }
+
+    #[test]
+    fn cannot_play_without_an_opponent() {
+        let mut g = TicTacToeGame::new();
+        g.add_player(1);
+        assert_eq!(
+            play(g.clone(), 1, vec![0, 0]).unwrap_err(),
+            MoveError::GameNotStarted
+        );
+        g.add_player(2);
+        g.remove_player(2);
+        assert_eq!(
+            play(g, 1, vec![0, 0]).unwrap_err(),
+            MoveError::GameNotStarted
+        );
+    }
+
+    #[test]
+    fn cannot_play_after_a_win() {
+        let g = game(1, 2, "xx1/oo1/3 x 3x3 3");
+        let won = play(g, 1, vec![2, 0]).unwrap();
+        assert_eq!(play(won, 2, vec![2, 1]).unwrap_err(), MoveError::GameOver);
+    }
+
+    #[test]
+    fn refused_move_through_the_trait_leaves_the_game_alone() {
+        let mut g: Box<dyn Game> = Box::new(game(1, 2, "x2/1o1/3 x 3x3 3"));
+        let before = g.state();
+        let turn = GameTurnMessage {
+            position: vec![1, 1],
+        };
+        assert_eq!(g.apply_move(1, &turn), Err(MoveError::Occupied));
+        assert_eq!(g.state(), before);
+        assert_eq!(g.legal_moves().len(), 7);
+        assert_eq!(g.seat_count(), 2);
+    }
+
+    #[test]
+    fn moves_are_recorded_in_order() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        let g = play(g, 1, vec![1, 1]).unwrap();
+        let g = play(g, 2, vec![0, 2]).unwrap();
+        let moved: Vec<(usize, Vec<usize>)> = g
+            .moves
+            .iter()
+            .map(|m| (m.player, m.turn.position.clone()))
+            .collect();
+        assert_eq!(moved, vec![(1, vec![1, 1]), (2, vec![0, 2])]);
+        let json = serde_json::to_value(&g).unwrap();
+        assert_eq!(json["moves"][1]["player"], 2);
+        assert_eq!(json["moves"][1]["position"][1], 2);
+    }
+
+    #[test]
+    fn rejoining_player_gets_the_turn_if_they_are_behind() {
+        let g = game(1, 2, "3/3/3 x 3x3 3");
+        let mut g = play(g, 1, vec![1, 1]).unwrap();
+        g.remove_player(2);
+        g.add_player(3);
+        assert_eq!(g.seating.current_player_turn, Some(3));
+    }
+
+    #[test]
+    fn forfeit_hands_the_game_to_the_opponent() {
+        let mut g = game(1, 2, "3/3/3 x 3x3 3");
+        Game::forfeit(&mut g, 1);
+        assert_eq!(g.winner, Some(2));
+        assert_eq!(g.seating.current_player_turn, None);
+        assert_eq!(play(g, 2, vec![0, 0]).unwrap_err(), MoveError::GameOver);
+    }
+
+    fn with_rules(position: &str, rules: Rules) -> TicTacToeGame {
+        let mut g = TicTacToeGame::from_position(position).unwrap();
+        g.rules = rules;
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    #[test]
+    fn misere_lines_lose() {
+        let rules = Rules {
+            misere: true,
+            ..Rules::default()
+        };
+        let g = with_rules("xx1/oo1/3 x 3x3 3", rules);
+        let g = play(g, 1, vec![2, 0]).unwrap();
+        assert_eq!(g.winner, Some(2));
+        assert!(g.outcome.is_over());
+    }
+
+    #[test]
+    fn wild_lines_count_for_whoever_made_them() {
+        let rules = Rules {
+            wild: true,
+            ..Rules::default()
+        };
+        let g = with_rules("xx1/oo1/3 o 3x3 3", rules);
+        assert_eq!(g.legal_moves().len(), 10);
+        assert_eq!(
+            play(g.clone(), 2, vec![2, 0, 3]).unwrap_err(),
+            MoveError::Malformed
+        );
+        // o finishes x's row with an x, and that's a win for o
+        let g = play(g, 2, vec![2, 0, 1]).unwrap();
+        assert_eq!(g.grid.get(2, 0), Cell::Taken(Seat::One));
+        assert_eq!(g.winner, Some(2));
+    }
+
+    #[test]
+    fn pie_rule_swaps_sides_after_the_first_move() {
+        let rules = Rules {
+            pie: true,
+            ..Rules::default()
+        };
+        let g = with_rules("3/3/3 x 3x3 3", rules);
+        assert_eq!(
+            play(g.clone(), 1, Vec::new()).unwrap_err(),
+            MoveError::CantSwap
+        );
+        let g = play(g, 1, vec![1, 1]).unwrap();
+        assert_eq!(g.legal_moves().len(), 9);
+        let g = play(g, 2, Vec::new()).unwrap();
+        assert_eq!(g.seating.player1, Some(2));
+        assert_eq!(g.seating.player2, Some(1));
+        assert_eq!(g.seating.current_player_turn, Some(1));
+        assert_eq!(g.seating.to_move, Seat::Two);
+        assert_eq!(
+            play(g.clone(), 1, Vec::new()).unwrap_err(),
+            MoveError::CantSwap
+        );
+        let g = play(g, 1, vec![0, 0]).unwrap();
+        assert_eq!(g.grid.get(0, 0), Cell::Taken(Seat::Two));
+        assert_eq!(serde_json::to_value(&g).unwrap()["rules"]["pie"], true);
+    }
+
+    #[test]
+    fn torus_lines_wrap_around_the_edges() {
+        let rules = Rules {
+            torus: true,
+            ..Rules::default()
+        };
+        // x's broken diagonal runs off the right edge and back in on the left
+        let g = with_rules("1x1/2x/2o o 3x3 3", rules);
+        let g = play(g, 2, vec![0, 0]).unwrap();
+        assert_eq!(g.outcome, GameOutcome::InProgress);
+        let g = play(g, 1, vec![0, 2]).unwrap();
+        assert_eq!(
+            g.outcome,
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![1, 0], vec![2, 1], vec![0, 2]],
+            }
+        );
+    }
+
+    #[test]
+    fn blocked_cells_cant_be_played_or_lined_up_through() {
+        let mut g = TicTacToeGame::new();
+        g.block(1, 1).unwrap();
+        assert!(g.block(1, 1).is_err());
+        assert!(g.block(3, 0).is_err());
+        g.add_player(1);
+        g.add_player(2);
+        assert_eq!(g.legal_moves().len(), 8);
+        assert_eq!(
+            play(g.clone(), 1, vec![1, 1]).unwrap_err(),
+            MoveError::Occupied
+        );
+        assert_eq!(serde_json::to_value(&g).unwrap()["grid"][1][1], 3);
+        assert_eq!(g.position(), "3/1#1/3 x 3x3 3");
+
+        let mut g = TicTacToeGame::with_size(4, 4, 3).unwrap();
+        g.block_random(5, &mut rand::thread_rng()).unwrap();
+        assert_eq!(g.grid.blocked().count(), 5);
+        assert!(g.block_random(12, &mut rand::thread_rng()).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+//! Ultimate tic-tac-toe: nine small boards laid out three by three. The
+//! square you play in inside a small board picks the small board your
+//! opponent has to play in next, and three small boards in a row wins.
+//!
+//! Moves are `[x, y]` on the whole 9x9 grid, so `[4, 4]` is the middle of
+//! the middle board and sends the opponent back to the middle board.
+
+use bitboard::{win_masks, Board};
+use game::{Cell, Game, GameOutcome, GameTurnMessage, MoveError, PlayedMove, Seat, Seating};
+use serde::{Deserialize, Serialize};
+
+/// squares along one side of a small board, and small boards along one side
+/// of the whole game
+const SIDE: usize = 3;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UltimateGame {
+    #[serde(flatten)]
+    pub seating: Seating,
+    /// the small boards, left to right then top to bottom, so board `[x, y]`
+    /// is `boards[y * 3 + x]`
+    pub boards: Vec<Board>,
+    /// who has won each small board, laid out like the boards themselves
+    pub board_winners: Board,
+    /// the small board the next move has to go in as `[x, y]`, `None` when
+    /// any open board will do
+    pub active_board: Option<(usize, usize)>,
+    pub winner: Option<usize>,
+    pub outcome: GameOutcome,
+    /// every move so far, oldest first
+    pub moves: Vec<PlayedMove>,
+}
+
+impl Default for UltimateGame {
+    fn default() -> Self {
+        UltimateGame {
+            seating: Seating::default(),
+            boards: vec![Board::new(SIDE, SIDE); SIDE * SIDE],
+            board_winners: Board::new(SIDE, SIDE),
+            active_board: None,
+            winner: None,
+            outcome: GameOutcome::InProgress,
+            moves: Vec::new(),
+        }
+    }
+}
+
+impl UltimateGame {
+    pub fn new() -> Self {
+        UltimateGame::default()
+    }
+
+    fn board(&self, bx: usize, by: usize) -> &Board {
+        &self.boards[by * SIDE + bx]
+    }
+
+    /// a small board nobody can play in any more, won or full
+    fn is_closed(&self, bx: usize, by: usize) -> bool {
+        self.board_winners.get(bx, by) != Cell::Empty || self.board(bx, by).is_full()
+    }
+
+    /// the small boards the next move may go in
+    fn open_boards(&self) -> Vec<(usize, usize)> {
+        match self.active_board {
+            Some(board) => vec![board],
+            None => (0..SIDE * SIDE)
+                .map(|i| (i % SIDE, i / SIDE))
+                .filter(|&(bx, by)| !self.is_closed(bx, by))
+                .collect(),
+        }
+    }
+
+    /// where the game stands once the small boards are settled
+    fn get_outcome(&self) -> GameOutcome {
+        let masks = win_masks(SIDE, SIDE, SIDE);
+        let winner = self
+            .board_winners
+            .completed_line(&masks)
+            .and_then(|(seat, line)| Some((self.seating.player_in(seat)?, line)));
+        if let Some((player, line)) = winner {
+            return GameOutcome::Won {
+                player,
+                cells: line
+                    .squares()
+                    .into_iter()
+                    .map(|(bx, by)| vec![bx, by])
+                    .collect(),
+            };
+        }
+        let all_closed = (0..SIDE * SIDE).all(|i| self.is_closed(i % SIDE, i / SIDE));
+        if all_closed {
+            GameOutcome::Draw
+        } else {
+            GameOutcome::InProgress
+        }
+    }
+
+    /// the seat of `player_id` and the square `turn` is for, if they may play
+    fn check_move(
+        &self,
+        player_id: usize,
+        turn: &GameTurnMessage,
+    ) -> Result<(Seat, usize, usize), MoveError> {
+        if self.outcome.is_over() {
+            return Err(MoveError::GameOver);
+        }
+        let seat = self.seating.check_turn(player_id)?;
+        let (x, y) = match turn.position.as_slice() {
+            [x, y] => (*x, *y),
+            _ => return Err(MoveError::Malformed),
+        };
+        if x >= SIDE * SIDE || y >= SIDE * SIDE {
+            return Err(MoveError::OutOfBounds);
+        }
+        if !self.open_boards().contains(&(x / SIDE, y / SIDE)) {
+            return Err(MoveError::WrongBoard);
+        }
+        if self.board(x / SIDE, y / SIDE).get(x % SIDE, y % SIDE) != Cell::Empty {
+            return Err(MoveError::Occupied);
+        }
+        Ok((seat, x, y))
+    }
+}
+
+impl Game for UltimateGame {
+    fn seats(&self) -> Vec<Option<usize>> {
+        self.seating.seats()
+    }
+
+    fn add_player(&mut self, id: usize) {
+        self.seating.join(id, &self.outcome);
+    }
+
+    fn remove_player(&mut self, id: usize) {
+        self.seating.remove_player(id)
+    }
+
+    fn current_player(&self) -> Option<usize> {
+        self.seating.current_player_turn
+    }
+
+    fn apply_move(&mut self, player_id: usize, turn: &GameTurnMessage) -> Result<(), MoveError> {
+        let (seat, x, y) = self.check_move(player_id, turn)?;
+        let (bx, by) = (x / SIDE, y / SIDE);
+        let (cx, cy) = (x % SIDE, y % SIDE);
+        self.boards[by * SIDE + bx].set(cx, cy, Cell::Taken(seat));
+        let masks = win_masks(SIDE, SIDE, SIDE);
+        if let Some((seat, _)) = self.board(bx, by).completed_line(&masks) {
+            self.board_winners.set(bx, by, Cell::Taken(seat));
+        }
+        // the square played in names the next board, unless it's closed
+        self.active_board = if self.is_closed(cx, cy) {
+            None
+        } else {
+            Some((cx, cy))
+        };
+        self.moves.push(PlayedMove {
+            player: player_id,
+            turn: turn.clone(),
+        });
+        self.seating.pass_turn(seat);
+        self.outcome = self.get_outcome();
+        self.winner = self.outcome.winner();
+        if self.outcome.is_over() {
+            self.seating.current_player_turn = None;
+            self.active_board = None;
+        }
+        Ok(())
+    }
+
+    fn legal_moves(&self) -> Vec<GameTurnMessage> {
+        if self.outcome.is_over() {
+            return Vec::new();
+        }
+        let mut moves = Vec::new();
+        for (bx, by) in self.open_boards() {
+            for (cx, cy) in self.board(bx, by).empty().squares() {
+                moves.push(GameTurnMessage {
+                    position: vec![bx * SIDE + cx, by * SIDE + cy],
+                });
+            }
+        }
+        moves
+    }
+
+    fn outcome(&self) -> GameOutcome {
+        self.outcome.clone()
+    }
+
+    fn forfeit(&mut self, player_id: usize) {
+        self.outcome = self.seating.forfeit(player_id, &self.outcome);
+        self.winner = self.outcome.winner();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use game::GameSnapshot;
+
+    fn seated() -> UltimateGame {
+        let mut g = UltimateGame::new();
+        g.add_player(1);
+        g.add_player(2);
+        g
+    }
+
+    fn play(g: &mut UltimateGame, player_id: usize, x: usize, y: usize) -> Result<(), MoveError> {
+        g.apply_move(
+            player_id,
+            &GameTurnMessage {
+                position: vec![x, y],
+            },
+        )
+    }
+
+    #[test]
+    fn your_square_picks_the_next_board() {
+        let mut g = seated();
+        assert_eq!(g.legal_moves().len(), 81);
+        // top right square of the middle board sends o to the top right board
+        play(&mut g, 1, 5, 3).unwrap();
+        assert_eq!(g.active_board, Some((2, 0)));
+        assert_eq!(g.legal_moves().len(), 9);
+        assert_eq!(play(&mut g, 2, 4, 4), Err(MoveError::WrongBoard));
+        play(&mut g, 2, 7, 1).unwrap();
+        assert_eq!(g.active_board, Some((1, 1)));
+        assert_eq!(play(&mut g, 1, 5, 3), Err(MoveError::Occupied));
+    }
+
+    #[test]
+    fn being_sent_to_a_closed_board_frees_you() {
+        let mut g = seated();
+        // x takes the top row of the top left board, ending on its top left
+        // square, which points back at the board x just won
+        for &(player, x, y) in &[(1, 1, 0), (2, 3, 0), (1, 2, 0), (2, 6, 0), (1, 0, 0)] {
+            play(&mut g, player, x, y).unwrap();
+        }
+        assert_eq!(g.board_winners.get(0, 0), Cell::Taken(Seat::One));
+        assert_eq!(g.active_board, None);
+        let moves = g.legal_moves();
+        assert_eq!(moves.len(), 81 - 9 - 2);
+        assert!(moves
+            .iter()
+            .all(|turn| turn.position[0] >= 3 || turn.position[1] >= 3));
+    }
+
+    #[test]
+    fn three_small_boards_in_a_row_wins() {
+        let mut g = seated();
+        for i in 0..3 {
+            for x in 0..3 {
+                g.boards[i].set(x, 0, Cell::Taken(Seat::One));
+            }
+            g.board_winners.set(i, 0, Cell::Taken(Seat::One));
+        }
+        assert_eq!(
+            g.get_outcome(),
+            GameOutcome::Won {
+                player: 1,
+                cells: vec![vec![0, 0], vec![1, 0], vec![2, 0]],
+            }
+        );
+    }
+
+    #[test]
+    fn state_describes_every_small_board() {
+        let mut g = seated();
+        play(&mut g, 1, 4, 4).unwrap();
+        let json = g.state();
+        assert_eq!(json["boards"].as_array().unwrap().len(), 9);
+        assert_eq!(json["boards"][4][1][1], 1);
+        assert_eq!(json["active_board"], serde_json::json!([1, 1]));
+        assert_eq!(json["current_player_turn"], 2);
+    }
+}